@@ -0,0 +1,351 @@
+use serde_json::de::IoRead;
+use serde::Deserialize;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::compact_graph::{CompactGraph, CompactGraphBuilder};
+use crate::index_calculator::Edge;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum NormalizeError {
+        Io(err: io::Error) {
+            from()
+            display("Could not read callgraph: {}", err)
+            cause(err)
+        }
+        Malformed(err: serde_json::Error) {
+            from()
+            display("Malformed callgraph: {}", err)
+            cause(err)
+        }
+        NodeIdMismatch(position: usize, id: usize) {
+            display("Function at position {} has id {}", position, id)
+        }
+        EdgeOutOfRange(source: usize, target: usize, node_count: usize) {
+            display("Call {} -> {} points outside of {} functions", source, target, node_count)
+        }
+        TooLarge(limit: usize) {
+            display("Callgraph has more than {} functions and calls", limit)
+        }
+    }
+}
+
+/// A function as Präzi writes it into `callgraph.json`, next to the calls
+/// stored as `[caller, callee, flag]` triples. Edge lists some graphs carry
+/// are ignored.
+#[derive(Deserialize)]
+struct RawFunction {
+    id: usize,
+    package_name: Option<String>,
+    package_version: Option<String>,
+    crate_name: String,
+    relative_def_id: String,
+    num_lines: isize,
+    is_externally_visible: bool
+}
+
+/// Reads a raw callgraph straight into a `CompactGraph`, one function or call
+/// at a time, so neither the file nor a `Node` per function is ever held in
+/// memory. With `max_size`, reading stops as soon as the graph has more
+/// functions and calls than that.
+pub fn stream_callgraph<R: Read>(reader: R, max_size: Option<usize>) -> Result<CompactGraph, NormalizeError>{
+    load_graph(reader, max_size, |loader, deserializer| loader.deserialize(deserializer))
+}
+
+/// Reads a graph regrouped by the old grapher.py, a list of `Node`s with their
+/// outward edges, the same way as `stream_callgraph`.
+pub fn stream_legacy_callgraph<R: Read>(reader: R, max_size: Option<usize>) -> Result<CompactGraph, NormalizeError>{
+    load_graph(reader, max_size, |loader, deserializer| LegacyNodes(loader).deserialize(deserializer))
+}
+
+fn load_graph<R, F>(reader: R, max_size: Option<usize>, read: F) -> Result<CompactGraph, NormalizeError>
+    where R: Read, F: FnOnce(&mut GraphLoader, &mut serde_json::Deserializer<IoRead<R>>) -> serde_json::Result<()>{
+    let mut loader = GraphLoader{ builder: CompactGraphBuilder::default(), max_size, largest_call: None, failure: None };
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let result = read(&mut loader, &mut deserializer).and_then(|_| deserializer.end());
+    // Failures of our own checks are passed through serde as plain messages.
+    if let Some(failure) = loader.failure {
+        return Err(failure);
+    }
+    result?;
+
+    // Calls may come before the functions they refer to.
+    let node_count = loader.builder.function_count();
+    if let Some((source, target)) = loader.largest_call {
+        if source.max(target) >= node_count {
+            return Err(NormalizeError::EdgeOutOfRange(source, target, node_count));
+        }
+    }
+    Ok(loader.builder.build())
+}
+
+struct GraphLoader {
+    builder: CompactGraphBuilder,
+    max_size: Option<usize>,
+    /// The call with the highest node id seen so far.
+    largest_call: Option<(usize, usize)>,
+    failure: Option<NormalizeError>
+}
+
+impl GraphLoader {
+    fn add_function(&mut self, function: RawFunction) -> Result<(), NormalizeError>{
+        let position = self.builder.function_count();
+        if function.id != position {
+            return Err(NormalizeError::NodeIdMismatch(position, function.id));
+        }
+        self.builder.add_function(function.package_name.as_deref(), function.package_version.as_deref(), &function.crate_name, &function.relative_def_id, function.num_lines, function.is_externally_visible)?;
+        self.check_size()
+    }
+
+    fn add_call(&mut self, (source, target, flag): (usize, usize, bool)) -> Result<(), NormalizeError>{
+        match self.largest_call {
+            Some((s, t)) if s.max(t) >= source.max(target) => (),
+            _ => self.largest_call = Some((source, target))
+        }
+        self.builder.add_call(source, target, flag)?;
+        self.check_size()
+    }
+
+    fn add_node(&mut self, node: RawLegacyNode) -> Result<(), NormalizeError>{
+        let source = node.function.id;
+        self.add_function(node.function)?;
+        for edge in node.outward_edges {
+            self.add_call((source, edge.target, edge.some_bool))?;
+        }
+        Ok(())
+    }
+
+    fn check_size(&self) -> Result<(), NormalizeError>{
+        match self.max_size {
+            Some(limit) if self.builder.function_count() + self.builder.call_count() > limit => Err(NormalizeError::TooLarge(limit)),
+            _ => Ok(())
+        }
+    }
+
+    /// Keeps `err` to be returned instead of the serde error built from it.
+    fn fail<E: de::Error>(&mut self, err: NormalizeError) -> E{
+        let serde_err = E::custom(&err);
+        self.failure = Some(err);
+        serde_err
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for &mut GraphLoader {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error>{
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for &mut GraphLoader {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result{
+        formatter.write_str("a callgraph with functions and function_calls")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error>{
+        let (mut functions, mut calls) = (false, false);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "functions" => {
+                    map.next_value_seed(Functions(&mut *self))?;
+                    functions = true;
+                },
+                "function_calls" => {
+                    map.next_value_seed(Calls(&mut *self))?;
+                    calls = true;
+                },
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if !functions {
+            return Err(de::Error::missing_field("functions"));
+        }
+        if !calls {
+            return Err(de::Error::missing_field("function_calls"));
+        }
+        Ok(())
+    }
+}
+
+/// The `functions` array, added to the graph element by element.
+struct Functions<'a>(&'a mut GraphLoader);
+
+impl<'de, 'a> DeserializeSeed<'de> for Functions<'a> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error>{
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for Functions<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result{
+        formatter.write_str("a list of functions")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error>{
+        while let Some(function) = seq.next_element::<RawFunction>()? {
+            if let Err(err) = self.0.add_function(function) {
+                return Err(self.0.fail(err));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `function_calls` array of `[caller, callee, flag]` triples.
+struct Calls<'a>(&'a mut GraphLoader);
+
+impl<'de, 'a> DeserializeSeed<'de> for Calls<'a> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error>{
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for Calls<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result{
+        formatter.write_str("a list of [caller, callee, flag] calls")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error>{
+        while let Some(call) = seq.next_element::<(usize, usize, bool)>()? {
+            if let Err(err) = self.0.add_call(call) {
+                return Err(self.0.fail(err));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A node of a legacy graph. Its inward edges mirror the outward ones and are
+/// skipped.
+#[derive(Deserialize)]
+struct RawLegacyNode {
+    #[serde(flatten)]
+    function: RawFunction,
+    #[serde(default)]
+    outward_edges: Vec<Edge>
+}
+
+/// The top level list of a legacy graph.
+struct LegacyNodes<'a>(&'a mut GraphLoader);
+
+impl<'de, 'a> DeserializeSeed<'de> for LegacyNodes<'a> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error>{
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for LegacyNodes<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result{
+        formatter.write_str("a list of nodes")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error>{
+        while let Some(node) = seq.next_element::<RawLegacyNode>()? {
+            if let Err(err) = self.0.add_node(node) {
+                return Err(self.0.fail(err));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(id: usize, package: &str) -> String {
+        format!(r#"{{"id":{},"package_name":"{}","package_version":"1.0.0","crate_name":"{}","relative_def_id":"{}[0]::f[{}]","num_lines":3,"is_externally_visible":true}}"#, id, package, package, package, id)
+    }
+
+    fn callgraph(functions: &[String], calls: &str) -> String {
+        format!(r#"{{"functions":[{}],"function_calls":{}}}"#, functions.join(","), calls)
+    }
+
+    fn stream(json: &str, max_size: Option<usize>) -> Result<CompactGraph, NormalizeError> {
+        stream_callgraph(json.as_bytes(), max_size)
+    }
+
+    #[test]
+    fn groups_calls_by_caller_and_callee() {
+        let json = callgraph(&[function(0, "a"), function(1, "b"), function(2, "b")], "[[0,1,true],[0,2,false],[1,2,true]]");
+        let graph = stream(&json, None).unwrap();
+        assert_eq!(graph.callees(0), &[1, 2]);
+        assert_eq!(graph.call_flags(0), &[true, false]);
+        assert_eq!(graph.callers(2), &[0, 1]);
+        assert!(graph.callers(0).is_empty());
+    }
+
+    #[test]
+    fn streams_calls_before_functions() {
+        let json = format!(r#"{{"function_calls":[[0,1,true],[1,2,false]],"functions":[{},{},{}]}}"#, function(0, "a"), function(1, "b"), function(2, "b"));
+        let graph = stream(&json, None).unwrap();
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.callees(0), &[1]);
+        assert_eq!(graph.call_flags(0), &[true]);
+        assert_eq!(graph.callers(2), &[1]);
+        assert_eq!(graph.package(1), Some("b"));
+    }
+
+    #[test]
+    fn streaming_rejects_ids_that_are_not_positions() {
+        let json = callgraph(&[function(0, "a"), function(2, "a")], "[]");
+        match stream(&json, None) {
+            Err(NormalizeError::NodeIdMismatch(1, 2)) => (),
+            other => panic!("unexpected result: {:?}", other.map(|g| g.len()))
+        }
+    }
+
+    #[test]
+    fn streaming_rejects_calls_outside_the_graph() {
+        let json = callgraph(&[function(0, "a"), function(1, "a")], "[[1,0,true],[0,3,false]]");
+        match stream(&json, None) {
+            Err(NormalizeError::EdgeOutOfRange(0, 3, 2)) => (),
+            other => panic!("unexpected result: {:?}", other.map(|g| g.len()))
+        }
+    }
+
+    #[test]
+    fn streaming_stops_at_the_size_limit() {
+        let json = callgraph(&[function(0, "a"), function(1, "a")], "[[0,1,true]]");
+        match stream(&json, Some(2)) {
+            Err(NormalizeError::TooLarge(2)) => (),
+            other => panic!("unexpected result: {:?}", other.map(|g| g.len()))
+        }
+        assert_eq!(stream(&json, Some(3)).unwrap().size(), 3);
+    }
+
+    #[test]
+    fn streams_legacy_nodes_with_their_outward_edges() {
+        let node = |id: usize, edges: &str| function(id, "a").replace(r#""num_lines""#, &format!(r#""outward_edges":{},"inward_edges":[{{"target":0,"some_bool":true}}],"num_lines""#, edges));
+        let json = format!("[{},{}]", node(0, r#"[{"target":1,"some_bool":true}]"#), node(1, "[]"));
+        let graph = stream_legacy_callgraph(json.as_bytes(), None).unwrap();
+        assert_eq!(graph.len(), 2);
+        assert_eq!(graph.callees(0), &[1]);
+        assert!(graph.callees(1).is_empty());
+        assert_eq!(graph.def_id(1), "a[0]::f[1]");
+
+        match stream_legacy_callgraph(json.as_bytes(), Some(2)) {
+            Err(NormalizeError::TooLarge(2)) => (),
+            other => panic!("unexpected result: {:?}", other.map(|g| g.len()))
+        }
+    }
+}
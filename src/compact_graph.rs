@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::mem::{size_of, size_of_val};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::callgraph::NormalizeError;
+use crate::index_calculator::{Edge, Node};
+
+/// Id of an interned string.
+pub type Symbol = u32;
+
+/// Node ids, call counts, symbols and def id offsets are stored as `u32`.
+pub const MAX_INDEX: usize = u32::MAX as usize;
+
+/// Stores each distinct string once. Package names, versions and crate names
+/// repeat across most functions of a graph. The table and the lookup map
+/// share the text of every string.
+#[derive(Default)]
+pub struct Interner {
+    strings: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, Symbol>
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(id) = self.ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as Symbol;
+        let shared: Arc<str> = Arc::from(s);
+        self.strings.push(shared.clone());
+        self.ids.insert(shared, id);
+        id
+    }
+
+    /// The symbol of `s` if it was interned.
+    pub fn lookup(&self, s: &str) -> Option<Symbol> {
+        self.ids.get(s).cloned()
+    }
+
+    pub fn resolve(&self, id: Symbol) -> &str {
+        &self.strings[id as usize]
+    }
+
+    /// All strings, in the order of their symbols.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.strings.iter().map(|s| &**s)
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// The text of every string once, behind the two reference counts of
+    /// its `Arc`.
+    fn memory(&self) -> usize {
+        let text: usize = self.strings.iter().map(|s| s.len() + 2 * size_of::<usize>()).sum();
+        text
+            + self.strings.capacity() * size_of::<Arc<str>>()
+            + self.ids.capacity() * (size_of::<Arc<str>>() + size_of::<Symbol>())
+    }
+}
+
+/// Bytes held by the parts of a `CompactGraph`.
+pub struct MemoryUsage {
+    pub strings: usize,
+    pub nodes: usize,
+    pub calls: usize
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.strings + self.nodes + self.calls
+    }
+}
+
+/// A callgraph with interned strings and calls in flat CSR arrays: the calls
+/// of node `n` are `callees[call_offsets[n]..call_offsets[n + 1]]`, and the
+/// same for callers. Nodes are addressed by their id.
+pub struct CompactGraph {
+    strings: Interner,
+    /// 0 for std functions, otherwise the symbol shifted by one.
+    packages: Vec<u32>,
+    versions: Vec<u32>,
+    crates: Vec<Symbol>,
+    /// All def ids back to back, node `n` spans `def_id_ends[n - 1]..def_id_ends[n]`.
+    def_ids: String,
+    def_id_ends: Vec<u32>,
+    num_lines: Vec<i32>,
+    externally_visible: Vec<bool>,
+    call_offsets: Vec<u32>,
+    callees: Vec<u32>,
+    call_flags: Vec<bool>,
+    caller_offsets: Vec<u32>,
+    callers: Vec<u32>
+}
+
+/// Collects functions and calls in any order and lays them out as a
+/// `CompactGraph`. Functions get ids in the order they are added.
+#[derive(Default)]
+pub struct CompactGraphBuilder {
+    strings: Interner,
+    packages: Vec<u32>,
+    versions: Vec<u32>,
+    crates: Vec<Symbol>,
+    def_ids: String,
+    def_id_ends: Vec<u32>,
+    num_lines: Vec<i32>,
+    externally_visible: Vec<bool>,
+    calls: Vec<(u32, u32, bool)>
+}
+
+impl CompactGraphBuilder {
+    /// Adds a function and returns its id. Fails once ids, symbols or def id
+    /// offsets no longer fit their `u32` fields.
+    pub fn add_function(&mut self, package: Option<&str>, version: Option<&str>, crate_name: &str, def_id: &str, num_lines: isize, is_externally_visible: bool) -> Result<usize, NormalizeError> {
+        // Up to three new strings, whose symbols are stored shifted by one.
+        if self.packages.len() >= MAX_INDEX || self.strings.len() + 3 >= MAX_INDEX || self.def_ids.len() + def_id.len() > MAX_INDEX {
+            return Err(NormalizeError::TooLarge(MAX_INDEX));
+        }
+        let strings = &mut self.strings;
+        self.packages.push(package.map_or(0, |p| strings.intern(p) + 1));
+        self.versions.push(version.map_or(0, |v| strings.intern(v) + 1));
+        self.crates.push(strings.intern(crate_name));
+        self.def_ids.push_str(def_id);
+        self.def_id_ends.push(self.def_ids.len() as u32);
+        self.num_lines.push(num_lines.max(i32::MIN as isize).min(i32::MAX as isize) as i32);
+        self.externally_visible.push(is_externally_visible);
+        Ok(self.packages.len() - 1)
+    }
+
+    /// Adds a call. Both functions have to be added before `build`.
+    pub fn add_call(&mut self, source: usize, target: usize, flag: bool) -> Result<(), NormalizeError> {
+        if source >= MAX_INDEX || target >= MAX_INDEX || self.calls.len() >= MAX_INDEX {
+            return Err(NormalizeError::TooLarge(MAX_INDEX));
+        }
+        self.calls.push((source as u32, target as u32, flag));
+        Ok(())
+    }
+
+    pub fn function_count(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn build(mut self) -> CompactGraph {
+        self.def_ids.shrink_to_fit();
+        let node_count = self.packages.len();
+        let (call_offsets, order) = group_by(node_count, self.calls.iter().map(|c| c.0));
+        let callees = order.iter().map(|i| self.calls[*i].1).collect();
+        let call_flags = order.iter().map(|i| self.calls[*i].2).collect();
+        let (caller_offsets, order) = group_by(node_count, self.calls.iter().map(|c| c.1));
+        let callers = order.iter().map(|i| self.calls[*i].0).collect();
+
+        CompactGraph {
+            strings: self.strings,
+            packages: self.packages,
+            versions: self.versions,
+            crates: self.crates,
+            def_ids: self.def_ids,
+            def_id_ends: self.def_id_ends,
+            num_lines: self.num_lines,
+            externally_visible: self.externally_visible,
+            call_offsets,
+            callees,
+            call_flags,
+            caller_offsets,
+            callers
+        }
+    }
+}
+
+/// Counting sort of calls by one of their ends. Returns the CSR offsets and
+/// the call indices in node order, keeping the insertion order per node.
+fn group_by<I: Iterator<Item = u32> + Clone>(node_count: usize, keys: I) -> (Vec<u32>, Vec<usize>) {
+    let mut offsets = vec![0u32; node_count + 1];
+    for key in keys.clone() {
+        offsets[key as usize + 1] += 1;
+    }
+    for n in 0..node_count {
+        offsets[n + 1] += offsets[n];
+    }
+    let mut next = offsets.clone();
+    let mut order = vec![0; offsets[node_count] as usize];
+    for (i, key) in keys.enumerate() {
+        order[next[key as usize] as usize] = i;
+        next[key as usize] += 1;
+    }
+    (offsets, order)
+}
+
+impl CompactGraph {
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// Ids of all nodes.
+    pub fn nodes(&self) -> Range<usize> {
+        0..self.len()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.callees.len()
+    }
+
+    /// Functions and calls together, what a maximum graph size is compared to.
+    pub fn size(&self) -> usize {
+        self.len() + self.call_count()
+    }
+
+    pub fn strings(&self) -> &Interner {
+        &self.strings
+    }
+
+    /// The symbol of a package name, version or crate name of the graph.
+    pub fn symbol(&self, s: &str) -> Option<Symbol> {
+        self.strings.lookup(s)
+    }
+
+    /// `None` for std functions.
+    pub fn package_symbol(&self, node: usize) -> Option<Symbol> {
+        optional_symbol(self.packages[node])
+    }
+
+    pub fn version_symbol(&self, node: usize) -> Option<Symbol> {
+        optional_symbol(self.versions[node])
+    }
+
+    pub fn package(&self, node: usize) -> Option<&str> {
+        self.package_symbol(node).map(|id| self.strings.resolve(id))
+    }
+
+    pub fn version(&self, node: usize) -> Option<&str> {
+        self.version_symbol(node).map(|id| self.strings.resolve(id))
+    }
+
+    pub fn crate_symbol(&self, node: usize) -> Symbol {
+        self.crates[node]
+    }
+
+    pub fn crate_name(&self, node: usize) -> &str {
+        self.strings.resolve(self.crates[node])
+    }
+
+    pub fn def_id(&self, node: usize) -> &str {
+        let start = if node == 0 { 0 } else { self.def_id_ends[node - 1] as usize };
+        &self.def_ids[start..self.def_id_ends[node] as usize]
+    }
+
+    pub fn num_lines(&self, node: usize) -> isize {
+        self.num_lines[node] as isize
+    }
+
+    /// Lines of code, with unknown sizes counted as 0.
+    pub fn loc(&self, node: usize) -> usize {
+        self.num_lines[node].max(0) as usize
+    }
+
+    pub fn is_externally_visible(&self, node: usize) -> bool {
+        self.externally_visible[node]
+    }
+
+    /// Nodes called by `node`, in the order of the source graph.
+    pub fn callees(&self, node: usize) -> &[u32] {
+        &self.callees[self.call_offsets[node] as usize..self.call_offsets[node + 1] as usize]
+    }
+
+    /// The flags of the calls returned by `callees`.
+    pub fn call_flags(&self, node: usize) -> &[bool] {
+        &self.call_flags[self.call_offsets[node] as usize..self.call_offsets[node + 1] as usize]
+    }
+
+    /// Nodes calling `node`.
+    pub fn callers(&self, node: usize) -> &[u32] {
+        &self.callers[self.caller_offsets[node] as usize..self.caller_offsets[node + 1] as usize]
+    }
+
+    /// Bytes the same graph would take as `Node`s with their own strings and
+    /// edge lists, to compare against `memory_usage`.
+    pub fn node_memory(&self) -> usize {
+        let strings: usize = self.nodes().map(|n| {
+            self.package(n).map_or(0, str::len) + self.version(n).map_or(0, str::len) + self.crate_name(n).len()
+        }).sum();
+        self.len() * size_of::<Node>() + strings + self.def_ids.len() + 2 * self.call_count() * size_of::<Edge>()
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            strings: self.strings.memory(),
+            nodes: vec_memory(&self.packages)
+                + vec_memory(&self.versions)
+                + vec_memory(&self.crates)
+                + self.def_ids.capacity()
+                + vec_memory(&self.def_id_ends)
+                + vec_memory(&self.num_lines)
+                + vec_memory(&self.externally_visible),
+            calls: vec_memory(&self.call_offsets)
+                + vec_memory(&self.callees)
+                + vec_memory(&self.call_flags)
+                + vec_memory(&self.caller_offsets)
+                + vec_memory(&self.callers)
+        }
+    }
+}
+
+fn optional_symbol(id: u32) -> Option<Symbol> {
+    if id == 0 { None } else { Some(id - 1) }
+}
+
+fn vec_memory<T>(v: &[T]) -> usize {
+    size_of_val(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_keys_by_node_in_insertion_order() {
+        let (offsets, order) = group_by(4, vec![2, 0, 2, 1].into_iter());
+        assert_eq!(offsets, vec![0, 1, 2, 4, 4]);
+        assert_eq!(order, vec![1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn lays_calls_out_in_both_directions() {
+        let mut builder = CompactGraphBuilder::default();
+        builder.add_function(Some("app"), Some("0.1.0"), "app", "app::main", 3, false).unwrap();
+        builder.add_function(Some("app"), Some("0.1.0"), "app", "app::run", -1, true).unwrap();
+        builder.add_function(None, None, "core", "core::fmt::write", 7, true).unwrap();
+        builder.add_call(1, 2, true).unwrap();
+        builder.add_call(0, 2, false).unwrap();
+        builder.add_call(0, 1, true).unwrap();
+        let graph = builder.build();
+
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.call_count(), 3);
+        assert_eq!(graph.callees(0), &[2, 1]);
+        assert_eq!(graph.call_flags(0), &[false, true]);
+        assert_eq!(graph.callees(1), &[2]);
+        assert!(graph.callees(2).is_empty());
+        assert_eq!(graph.callers(2), &[1, 0]);
+        assert_eq!(graph.callers(1), &[0]);
+        assert!(graph.callers(0).is_empty());
+
+        assert_eq!(graph.def_id(0), "app::main");
+        assert_eq!(graph.def_id(2), "core::fmt::write");
+        assert_eq!((graph.num_lines(1), graph.loc(1)), (-1, 0));
+        assert_eq!(graph.package(2), None);
+        assert_eq!(graph.package_symbol(0), graph.symbol("app"));
+        assert_eq!(graph.strings().len(), 3);
+    }
+
+    #[test]
+    fn rejects_calls_beyond_u32_ids() {
+        let mut builder = CompactGraphBuilder::default();
+        builder.add_function(None, None, "core", "core::f", 1, true).unwrap();
+        assert!(matches!(builder.add_call(0, MAX_INDEX, false), Err(NormalizeError::TooLarge(MAX_INDEX))));
+        assert_eq!(builder.call_count(), 0);
+    }
+}
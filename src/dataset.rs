@@ -0,0 +1,392 @@
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use semver::{Version, VersionReq};
+
+use crate::errors::AnalysisError;
+use crate::export::Exporter;
+use crate::index_calculator::{get_index, Metrics};
+use crate::sqlite_handler::{MainCrateState, SqliteHandler};
+
+/// Where a Präzi dataset snapshot lives and which of its crates to process.
+pub struct DatasetOptions {
+    pub data_path: PathBuf,
+    pub cache_path: PathBuf,
+    pub crate_filter: Vec<String>,
+    pub limit: Option<usize>,
+    pub versions: VersionSelection,
+    pub jobs: usize,
+    pub resume: bool,
+    pub force: bool,
+    pub ignore_state: bool,
+    /// Keep a binary cache of every parsed callgraph in the cache directory.
+    pub graph_cache: bool,
+    /// Crate versions whose callgraph has more functions and calls are
+    /// recorded as too large instead of analyzed.
+    pub max_graph_size: Option<usize>
+}
+
+/// Which version folders of a crate get analyzed.
+pub enum VersionSelection {
+    /// The highest version, which may be a pre-release.
+    Highest,
+    All,
+    Latest(usize),
+    /// Versions in a semver range, pre-releases only if the range names one.
+    Matching(VersionReq)
+}
+
+impl VersionSelection {
+    /// Picks the versions to analyze, in ascending order.
+    fn select(&self, mut versions: Vec<(Version, String, PathBuf)>) -> Vec<(Version, String, PathBuf)> {
+        versions.sort_by(|a, b| a.0.cmp(&b.0));
+        match self {
+            VersionSelection::Highest => {
+                let skip = versions.len().saturating_sub(1);
+                versions.into_iter().skip(skip).collect()
+            },
+            VersionSelection::All => versions,
+            VersionSelection::Latest(count) => {
+                let skip = versions.len().saturating_sub(*count);
+                versions.into_iter().skip(skip).collect()
+            },
+            VersionSelection::Matching(req) => versions.into_iter().filter(|v| req.matches(&v.0)).collect()
+        }
+    }
+}
+
+/// A single crate version waiting to be analyzed.
+struct CrateJob {
+    crate_name: String,
+    crate_version: String,
+    version: Version,
+    crate_path: PathBuf,
+    update_path: PathBuf
+}
+
+/// The jobs found in a dataset and the version folders that could not be
+/// parsed, with the reason.
+type CollectedJobs = (Vec<CrateJob>, Vec<(String, String, AnalysisError)>);
+
+/// Walks `<data_path>/<crate>/<version>` and collects the selected versions of
+/// every crate that passes the filters and is not in `processed`. When Präzi
+/// states are given, versions without a full callgraph are left out. Version
+/// folders that are not valid semver are returned as errors. Fails if the data
+/// directory cannot be read.
+fn collect_jobs(options: &DatasetOptions, processed: &HashSet<(String, String)>, states: &Option<HashMap<(String, String), MainCrateState>>) -> io::Result<CollectedJobs>{
+    let mut jobs = Vec::new();
+    let mut invalid = Vec::new();
+    let mut counter = 0;
+    let paths = fs::read_dir(&options.data_path)?;
+    for path in paths {
+        if let Some(limit) = options.limit {
+            if counter >= limit {
+                break;
+            }
+        }
+
+        let pather = path?;
+        let crate_name = pather.file_name().to_string_lossy().to_string();
+        if !options.crate_filter.is_empty() && !options.crate_filter.contains(&crate_name) {
+            continue;
+        }
+
+        match fs::read_dir(pather.path()){
+            Err(why) => {
+                println!("Failed reading versions in {:?} - {:?}", pather, why);
+            },
+            Ok(versions) => {
+                let mut found = Vec::new();
+                for version_folder in versions{
+                    let version_folder = version_folder?;
+                    let crate_path = version_folder.path();
+                    let crate_version = version_folder.file_name().to_string_lossy().to_string();
+                    match Version::parse(&crate_version) {
+                        Err(why) => {
+                            if !processed.contains(&(crate_name.to_string(), crate_version.to_string())) {
+                                invalid.push((crate_name.to_string(), crate_version.to_string(), AnalysisError::InvalidVersionFolder(crate_version, why)));
+                            }
+                        },
+                        Ok(v) => found.push((v, crate_version, crate_path))
+                    }
+                }
+
+                for (version, crate_version, crate_path) in options.versions.select(found) {
+                    let key = (crate_name.to_string(), crate_version.to_string());
+                    if processed.contains(&key) {
+                        continue;
+                    }
+                    if let Some(states) = states {
+                        let state = states.get(&key).cloned().unwrap_or(MainCrateState::Unknown);
+                        if !state.has_full_callgraph() {
+                            println!("Skipping {} - {}, Präzi state is {:?}", &crate_name, &crate_version, state);
+                            continue;
+                        }
+                    }
+                    jobs.push(CrateJob {
+                        update_path: options.cache_path.join(&crate_name).join(&crate_version),
+                        crate_name: crate_name.to_string(),
+                        crate_version,
+                        version,
+                        crate_path
+                    });
+                }
+                counter+=1;
+            }
+        }
+    }
+
+    Ok((jobs, invalid))
+}
+
+/// Analyzes the collected crates on `options.jobs` worker threads. Results are
+/// funnelled back to the calling thread, which is the only one writing to the
+/// database, one row per crate version.
+pub fn analyze_dataset(options: &DatasetOptions, db_handler: &SqliteHandler) -> io::Result<()>{
+    println!("Processing data in {:?}", options.data_path);
+    let processed = if options.resume {
+        db_handler.get_processed_crates()
+    } else {
+        HashSet::new()
+    };
+    let states = if !options.ignore_state && db_handler.has_crate_states() {
+        match db_handler.get_crate_states() {
+            Err(why) => {
+                println!("Could not read the Präzi states, analyzing every crate: {}", why);
+                None
+            },
+            Ok(states) => Some(states)
+        }
+    } else {
+        None
+    };
+    let (jobs, invalid) = collect_jobs(options, &processed, &states)?;
+    if options.resume {
+        println!("Resuming, {} crate versions already processed", processed.len());
+    }
+    for (crate_name, crate_version, why) in invalid {
+        println!("Skipping {} - {}: {}", &crate_name, &crate_version, why);
+        record_error(options, db_handler, &why, &crate_name, &crate_version);
+    }
+
+    run_jobs(options, jobs, |counter, total_jobs, job, result| {
+        match result {
+            Err(why) => {
+                record_error(options, db_handler, &why, &job.crate_name, &job.crate_version);
+                println!("Failed for {} - {}: {}", &job.crate_name, &job.version, why);
+            },
+            Ok(val) => {
+                let stored = if options.force {
+                    db_handler.replace_metric(&val, &job.crate_name, &job.crate_version)
+                } else {
+                    db_handler.insert_metric(&val, &job.crate_name, &job.crate_version)
+                };
+                match stored {
+                    Err(why) => {
+                        let why = AnalysisError::Persist(why);
+                        record_error(options, db_handler, &why, &job.crate_name, &job.crate_version);
+                        println!("Failed for {} - {}: {}", &job.crate_name, &job.version, why);
+                    },
+                    Ok(_) => {
+                        db_handler.set_analysis_state(&job.crate_name, &job.crate_version, MainCrateState::Analyzed);
+                        println!("Success ({}/{}) for - {} - {}", counter, total_jobs, &job.crate_name, &job.version);
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Analyzes the collected crates on `options.jobs` worker threads and hands
+/// every result to `handle` on the calling thread, together with how many
+/// results came in so far and how many crates there are in total.
+fn run_jobs<F>(options: &DatasetOptions, jobs: Vec<CrateJob>, mut handle: F)
+    where F: FnMut(usize, usize, CrateJob, Result<Metrics, AnalysisError>) {
+    let total_jobs = jobs.len();
+    let queue = Arc::new(Mutex::new(jobs.into_iter()));
+    let (sender, receiver) = mpsc::channel();
+    let graph_cache = options.graph_cache;
+    let max_graph_size = options.max_graph_size;
+
+    let workers: Vec<_> = (0..options.jobs.max(1)).map(|_| {
+        let queue = Arc::clone(&queue);
+        let sender = sender.clone();
+        thread::spawn(move || loop {
+            let job = match queue.lock().unwrap().next() {
+                None => break,
+                Some(job) => job
+            };
+            println!("Processing {} - {}", &job.crate_name, &job.version);
+            // A panic would take the worker and its crate with it, it is
+            // recorded as a failure of the crate instead.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                get_index(&job.crate_path, &job.update_path, &job.crate_name, &job.crate_version, graph_cache, max_graph_size)
+            })).unwrap_or_else(|payload| Err(AnalysisError::Panicked(panic_message(payload))));
+            let result = result.map(|(metrics, cache_error)| {
+                if let Some(why) = cache_error {
+                    println!("Could not cache the callgraph of {} - {} in {:?}: {}", &job.crate_name, &job.version, &job.update_path, why);
+                }
+                metrics
+            });
+            if sender.send((job, result)).is_err() {
+                break;
+            }
+        })
+    }).collect();
+    drop(sender);
+
+    for (counter, (job, result)) in receiver.iter().enumerate() {
+        handle(counter + 1, total_jobs, job, result);
+    }
+
+    for worker in workers {
+        if worker.join().is_err() {
+            println!("A worker thread panicked, its crate was not recorded");
+        }
+    }
+}
+
+/// The message a panic was started with, if it has one.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string()
+        }
+    }
+}
+
+/// Analyzes the collected crates like `analyze_dataset` but writes the results
+/// to export files instead of the database.
+pub fn export_dataset(options: &DatasetOptions, exporter: &mut Exporter) -> io::Result<()>{
+    println!("Processing data in {:?}", options.data_path);
+    let (jobs, invalid) = collect_jobs(options, &HashSet::new(), &None)?;
+    for (crate_name, crate_version, why) in invalid {
+        println!("Skipping {} - {}: {}", &crate_name, &crate_version, why);
+    }
+
+    run_jobs(options, jobs, |counter, total_jobs, job, result| {
+        match result.map(|val| exporter.write(&val, &job.crate_name, &job.crate_version)) {
+            Err(why) => println!("Failed for {} - {}: {}", &job.crate_name, &job.version, why),
+            Ok(Err(why)) => println!("Failed exporting {} - {}: {}", &job.crate_name, &job.version, why),
+            Ok(Ok(_)) => println!("Exported ({}/{}) - {} - {}", counter, total_jobs, &job.crate_name, &job.version)
+        }
+    });
+
+    if let Err(why) = exporter.finish() {
+        println!("Failed exporting: {}", why);
+    }
+    Ok(())
+}
+
+fn record_error(options: &DatasetOptions, db_handler: &SqliteHandler, why: &AnalysisError, crate_name: &String, crate_version: &String){
+    if options.force {
+        if let Err(why) = db_handler.replace_error(why, crate_name, crate_version) {
+            println!("Could not record the failure of {} - {}: {}", crate_name, crate_version, why);
+        }
+    } else {
+        db_handler.insert_error(why, crate_name, crate_version);
+    }
+    let state = match why {
+        AnalysisError::GraphTooLarge(..) => MainCrateState::SkippedTooLarge,
+        _ => MainCrateState::AnalysisFailed
+    };
+    db_handler.set_analysis_state(crate_name, crate_version, state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn select(selection: VersionSelection, folders: &[&str]) -> Vec<String> {
+        let versions = folders.iter()
+            .map(|f| (Version::parse(f).unwrap(), f.to_string(), PathBuf::from(f)))
+            .collect();
+        selection.select(versions).into_iter().map(|(_, folder, _)| folder).collect()
+    }
+
+    const FOLDERS: &[&str] = &["0.10.0", "0.2.0", "1.0.0-beta.1", "0.9.1", "1.0.0"];
+
+    #[test]
+    fn selects_the_highest_version() {
+        assert_eq!(select(VersionSelection::Highest, FOLDERS), vec!["1.0.0"]);
+        assert_eq!(select(VersionSelection::Highest, &["0.9.0", "1.0.0-alpha"]), vec!["1.0.0-alpha"]);
+        assert!(select(VersionSelection::Highest, &[]).is_empty());
+    }
+
+    #[test]
+    fn selects_all_versions_in_semver_order() {
+        assert_eq!(select(VersionSelection::All, FOLDERS), vec!["0.2.0", "0.9.1", "0.10.0", "1.0.0-beta.1", "1.0.0"]);
+    }
+
+    #[test]
+    fn selects_the_latest_versions() {
+        assert_eq!(select(VersionSelection::Latest(2), FOLDERS), vec!["1.0.0-beta.1", "1.0.0"]);
+        assert_eq!(select(VersionSelection::Latest(10), FOLDERS).len(), FOLDERS.len());
+        assert!(select(VersionSelection::Latest(0), FOLDERS).is_empty());
+    }
+
+    #[test]
+    fn selects_versions_matching_a_range() {
+        let matching = |req: &str| select(VersionSelection::Matching(VersionReq::parse(req).unwrap()), FOLDERS);
+        assert_eq!(matching("^0.9"), vec!["0.9.1"]);
+        assert_eq!(matching(">=0.9, <1.0.0"), vec!["0.9.1", "0.10.0"]);
+        assert_eq!(matching(">=0.9"), vec!["0.9.1", "0.10.0", "1.0.0"]);
+        assert_eq!(matching(">=1.0.0-beta"), vec!["1.0.0-beta.1", "1.0.0"]);
+    }
+
+    /// A data directory of its own per test, tests run in parallel.
+    fn data_path(test: &str) -> PathBuf {
+        env::temp_dir().join(format!("dataset_{}_{}", process::id(), test))
+    }
+
+    fn options(data_path: PathBuf) -> DatasetOptions {
+        DatasetOptions {
+            cache_path: data_path.join("cache"),
+            data_path,
+            crate_filter: Vec::new(),
+            limit: None,
+            versions: VersionSelection::All,
+            jobs: 1,
+            resume: false,
+            force: false,
+            ignore_state: false,
+            graph_cache: false,
+            max_graph_size: None
+        }
+    }
+
+    #[test]
+    fn collects_unparsable_version_folders_as_errors() {
+        let path = data_path("unparsable");
+        for folder in &["app/0.1.0", "app/0.2.0", "app/latest", "bpp/1.0.0"] {
+            fs::create_dir_all(path.join(folder)).unwrap();
+        }
+        let result = collect_jobs(&options(path.clone()), &HashSet::new(), &None);
+        fs::remove_dir_all(&path).unwrap();
+
+        let (jobs, invalid) = result.unwrap();
+        let mut jobs: Vec<_> = jobs.iter().map(|j| format!("{} {}", j.crate_name, j.crate_version)).collect();
+        jobs.sort();
+        assert_eq!(jobs, vec!["app 0.1.0", "app 0.2.0", "bpp 1.0.0"]);
+        assert_eq!(invalid.len(), 1);
+        assert_eq!((invalid[0].0.as_str(), invalid[0].1.as_str()), ("app", "latest"));
+        match invalid[0].2 {
+            AnalysisError::InvalidVersionFolder(..) => (),
+            ref other => panic!("unexpected error: {}", other)
+        }
+    }
+
+    #[test]
+    fn fails_on_a_missing_data_directory() {
+        assert!(analyze_dataset(&options(data_path("missing")), &SqliteHandler::new(":memory:")).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+
+/// One `::` separated part of a def path after the crate.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Segment {
+    /// A module, type, function or other named item with its index among
+    /// items of the same name.
+    Named(String, u32),
+    /// An `impl` block, numbered within its parent.
+    Impl(u32),
+    Closure(u32),
+    /// Items without a name, such as `extern` blocks.
+    Anonymous(u32),
+    /// Other compiler generated items, like `{{constructor}}`.
+    Generated(String, u32)
+}
+
+impl Segment {
+    fn parse(segment: &str) -> Segment {
+        let (name, index) = split_brackets(segment);
+        let index = index.and_then(|i| i.parse().ok()).unwrap_or(0);
+        match name {
+            "" => Segment::Anonymous(index),
+            "{{impl}}" => Segment::Impl(index),
+            "{{closure}}" => Segment::Closure(index),
+            _ if name.starts_with("{{") => Segment::Generated(name.trim_matches(|c| c == '{' || c == '}').to_string(), index),
+            _ => Segment::Named(name.to_string(), index)
+        }
+    }
+
+    fn is_generated(&self) -> bool {
+        matches!(self, Segment::Impl(_) | Segment::Closure(_) | Segment::Generated(_, _))
+    }
+}
+
+/// `name[inner]` into `name` and `inner`.
+fn split_brackets(segment: &str) -> (&str, Option<&str>) {
+    match segment.rfind('[') {
+        Some(open) if segment.ends_with(']') => (&segment[..open], Some(&segment[open + 1..segment.len() - 1])),
+        _ => (segment, None)
+    }
+}
+
+/// The crate name def ids use for a package, which has underscores for dashes.
+pub fn crate_name_of(package: &str) -> String {
+    package.replace('-', "_")
+}
+
+/// A parsed `relative_def_id`, e.g.
+/// `serde[a1b2]::de[0]::{{impl}}[3]::deserialize[0]`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DefPath {
+    pub crate_name: String,
+    /// Hash telling apart crates of the same name, differs between versions.
+    pub disambiguator: Option<String>,
+    pub segments: Vec<Segment>
+}
+
+impl DefPath {
+    /// Returns `None` for ids without a crate name.
+    pub fn parse(def_id: &str) -> Option<DefPath> {
+        let mut parts = def_id.split("::");
+        let (crate_name, disambiguator) = split_brackets(parts.next()?);
+        if crate_name.is_empty() {
+            return None;
+        }
+        Some(DefPath {
+            crate_name: crate_name.to_string(),
+            disambiguator: disambiguator.map(String::from),
+            segments: parts.map(Segment::parse).collect()
+        })
+    }
+
+    /// Position of the first impl block, closure or generated segment.
+    fn first_generated(&self) -> Option<usize> {
+        self.segments.iter().position(Segment::is_generated)
+    }
+
+    /// Name of the item itself, or of the function a closure belongs to.
+    pub fn item_name(&self) -> Option<&str> {
+        let end = match self.first_generated() {
+            Some(position) if !matches!(self.segments[position], Segment::Impl(_)) => position,
+            _ => self.segments.len()
+        };
+        self.segments[..end].iter().rev().find_map(|s| match s {
+            Segment::Named(name, _) => Some(name.as_str()),
+            _ => None
+        })
+    }
+
+    /// The crate and modules an item is declared in, e.g. `serde::de`.
+    /// Methods belong to the module of their impl block and closures to the
+    /// module of their function. Nested functions can't be told apart from
+    /// modules and count as one.
+    pub fn module_path(&self) -> String {
+        let mut modules = vec![self.crate_name.as_str()];
+        let (end, drop_item) = match self.first_generated() {
+            Some(position) => (position, !matches!(self.segments[position], Segment::Impl(_))),
+            None => (self.segments.len(), true)
+        };
+        let mut named: Vec<&str> = self.segments[..end].iter()
+            .filter_map(|s| match s {
+                Segment::Named(name, _) => Some(name.as_str()),
+                _ => None
+            })
+            .collect();
+        if drop_item {
+            named.pop();
+        }
+        modules.append(&mut named);
+        modules.join("::")
+    }
+
+    /// The module path and impl block of a method, e.g. `serde::de::{impl#3}`.
+    /// The def path doesn't name the implementing type, so impl blocks are
+    /// told apart by their index only.
+    pub fn impl_path(&self) -> Option<String> {
+        match self.first_generated().map(|position| &self.segments[position]) {
+            Some(Segment::Impl(index)) => Some(format!("{}::{{impl#{}}}", self.module_path(), index)),
+            _ => None
+        }
+    }
+
+    /// The def id without the crate disambiguator, equal for the same item
+    /// in different versions of a crate.
+    pub fn without_disambiguator(&self) -> String {
+        let mut id = self.crate_name.to_string();
+        for segment in &self.segments {
+            id.push_str("::");
+            match segment {
+                Segment::Named(name, index) => id.push_str(&format!("{}[{}]", name, index)),
+                Segment::Impl(index) => id.push_str(&format!("{{{{impl}}}}[{}]", index)),
+                Segment::Closure(index) => id.push_str(&format!("{{{{closure}}}}[{}]", index)),
+                Segment::Anonymous(index) => id.push_str(&format!("[{}]", index)),
+                Segment::Generated(name, index) => id.push_str(&format!("{{{{{}}}}}[{}]", name, index))
+            }
+        }
+        id
+    }
+}
+
+/// Readable form without disambiguators, e.g. `serde::de::{impl#3}::deserialize`.
+impl fmt::Display for DefPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.crate_name)?;
+        for segment in &self.segments {
+            match segment {
+                Segment::Named(name, _) => write!(f, "::{}", name)?,
+                Segment::Impl(index) => write!(f, "::{{impl#{}}}", index)?,
+                Segment::Closure(index) => write!(f, "::{{closure#{}}}", index)?,
+                Segment::Anonymous(_) => (),
+                Segment::Generated(name, index) => write!(f, "::{{{}#{}}}", name, index)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(def_id: &str) -> DefPath {
+        DefPath::parse(def_id).unwrap()
+    }
+
+    #[test]
+    fn parses_anonymous_segments() {
+        let path = parse("pango_sys[3ddd]::[0]::pango_coverage_copy[0]");
+        assert_eq!(path.crate_name, "pango_sys");
+        assert_eq!(path.disambiguator.as_deref(), Some("3ddd"));
+        assert_eq!(path.segments, vec![Segment::Anonymous(0), Segment::Named("pango_coverage_copy".to_string(), 0)]);
+        assert_eq!(path.item_name(), Some("pango_coverage_copy"));
+        assert_eq!(path.module_path(), "pango_sys");
+        assert_eq!(path.impl_path(), None);
+        assert_eq!(path.to_string(), "pango_sys::pango_coverage_copy");
+    }
+
+    #[test]
+    fn parses_methods_of_impl_blocks() {
+        let path = parse("serde[a1b2]::de[0]::{{impl}}[3]::deserialize[0]");
+        assert_eq!(path.segments[1], Segment::Impl(3));
+        assert_eq!(path.item_name(), Some("deserialize"));
+        assert_eq!(path.module_path(), "serde::de");
+        assert_eq!(path.impl_path().as_deref(), Some("serde::de::{impl#3}"));
+        assert_eq!(path.to_string(), "serde::de::{impl#3}::deserialize");
+        assert_eq!(path.without_disambiguator(), "serde::de[0]::{{impl}}[3]::deserialize[0]");
+    }
+
+    #[test]
+    fn closures_belong_to_their_function() {
+        let path = parse("app[1111]::util[0]::helper[0]::{{closure}}[1]");
+        assert_eq!(path.segments[2], Segment::Closure(1));
+        assert_eq!(path.item_name(), Some("helper"));
+        assert_eq!(path.module_path(), "app::util");
+        assert_eq!(path.impl_path(), None);
+        assert_eq!(path.to_string(), "app::util::helper::{closure#1}");
+    }
+
+    #[test]
+    fn parses_generated_segments() {
+        let path = parse("foo[ab12]::Bar[0]::{{constructor}}[0]");
+        assert_eq!(path.segments[1], Segment::Generated("constructor".to_string(), 0));
+        assert_eq!(path.item_name(), Some("Bar"));
+        assert_eq!(path.module_path(), "foo");
+        assert_eq!(path.to_string(), "foo::Bar::{constructor#0}");
+        assert_eq!(path.without_disambiguator(), "foo::Bar[0]::{{constructor}}[0]");
+    }
+
+    #[test]
+    fn versions_share_the_id_without_disambiguator() {
+        let old = parse("rand[1111]::rngs[0]::thread_rng[0]");
+        let new = parse("rand[2222]::rngs[0]::thread_rng[0]");
+        assert_ne!(old, new);
+        assert_eq!(old.without_disambiguator(), new.without_disambiguator());
+        assert_eq!(old.to_string(), "rand::rngs::thread_rng");
+    }
+
+    #[test]
+    fn needs_a_crate_name() {
+        assert_eq!(DefPath::parse(""), None);
+        assert_eq!(DefPath::parse("[0]::f[0]"), None);
+        assert_eq!(parse("core").segments, Vec::new());
+    }
+
+    #[test]
+    fn maps_package_names_to_crate_names() {
+        assert_eq!(crate_name_of("pango-sys"), "pango_sys");
+        assert_eq!(crate_name_of("serde"), "serde");
+    }
+}
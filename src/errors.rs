@@ -0,0 +1,125 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::callgraph::NormalizeError;
+
+quick_error! {
+    /// Everything that can make the analysis of a single crate version fail.
+    #[derive(Debug)]
+    pub enum AnalysisError {
+        MissingLockfile(path: PathBuf) {
+            display("No Cargo.lock in {:?}", path)
+        }
+        MalformedLockfile(err: cargo_lock::Error) {
+            display("Could not parse Cargo.lock: {}", err)
+        }
+        PackageNotInLockfile(name: String, version: String) {
+            display("Package {} - {} is not in Cargo.lock", name, version)
+        }
+        InvalidVersionFolder(folder: String, err: semver::SemVerError) {
+            display("Version folder {} is not a semver version: {}", folder, err)
+            cause(err)
+        }
+        CallgraphMissing(path: PathBuf) {
+            display("No callgraph in {:?}", path)
+        }
+        CallgraphMalformed(err: serde_json::Error) {
+            from()
+            display("Malformed callgraph: {}", err)
+            cause(err)
+        }
+        GraphTooLarge(limit: usize) {
+            display("Callgraph has more than {} functions and calls", limit)
+        }
+        NormalizeFailed(err: NormalizeError) {
+            display("Could not normalize callgraph: {}", err)
+            cause(err)
+        }
+        Io(err: io::Error) {
+            from()
+            display("I/O error: {}", err)
+            cause(err)
+        }
+        Classify(dependency: String, err: Box<AnalysisError>) {
+            display("Classifying dependency {} failed: {}", dependency, err)
+            cause(&**err)
+        }
+        Panicked(message: String) {
+            display("Analysis panicked: {}", message)
+        }
+        Persist(err: rusqlite::Error) {
+            display("Could not store results: {}", err)
+            cause(err)
+        }
+    }
+}
+
+/// The step of the pipeline a crate version failed in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Lockfile,
+    Normalize,
+    LoadGraph,
+    Classify,
+    Persist
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Lockfile => "lockfile",
+            Stage::Normalize => "normalize",
+            Stage::LoadGraph => "load_graph",
+            Stage::Classify => "classify",
+            Stage::Persist => "persist"
+        }
+    }
+}
+
+impl From<NormalizeError> for AnalysisError {
+    fn from(err: NormalizeError) -> Self {
+        match err {
+            NormalizeError::Malformed(err) => AnalysisError::CallgraphMalformed(err),
+            NormalizeError::TooLarge(limit) => AnalysisError::GraphTooLarge(limit),
+            err => AnalysisError::NormalizeFailed(err)
+        }
+    }
+}
+
+impl AnalysisError {
+    /// Stable name of the failure category, stored next to the message.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AnalysisError::MissingLockfile(..) => "missing_lockfile",
+            AnalysisError::MalformedLockfile(..) => "malformed_lockfile",
+            AnalysisError::PackageNotInLockfile(..) => "package_not_in_lockfile",
+            AnalysisError::InvalidVersionFolder(..) => "invalid_version_folder",
+            AnalysisError::CallgraphMissing(..) => "callgraph_missing",
+            AnalysisError::CallgraphMalformed(..) => "callgraph_malformed",
+            AnalysisError::GraphTooLarge(..) => "skipped_too_large",
+            AnalysisError::NormalizeFailed(..) => "normalize_failed",
+            AnalysisError::Io(..) => "io",
+            AnalysisError::Classify(_, err) => err.kind(),
+            AnalysisError::Panicked(..) => "panicked",
+            AnalysisError::Persist(..) => "persist_failed"
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        match self {
+            AnalysisError::MissingLockfile(..) |
+            AnalysisError::MalformedLockfile(..) |
+            AnalysisError::PackageNotInLockfile(..) |
+            AnalysisError::InvalidVersionFolder(..) => Stage::Lockfile,
+            AnalysisError::CallgraphMalformed(..) |
+            AnalysisError::NormalizeFailed(..) => Stage::Normalize,
+            AnalysisError::CallgraphMissing(..) |
+            AnalysisError::GraphTooLarge(..) |
+            AnalysisError::Io(..) => Stage::LoadGraph,
+            // Most of the remaining unwraps sit in the classification.
+            AnalysisError::Classify(..) |
+            AnalysisError::Panicked(..) => Stage::Classify,
+            AnalysisError::Persist(..) => Stage::Persist
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use serde::Serialize;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::index_calculator::Metrics;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ExportError {
+        Io(err: io::Error) {
+            from()
+            display("Could not write export: {}", err)
+            cause(err)
+        }
+        Json(err: serde_json::Error) {
+            from()
+            display("Could not serialize to JSON: {}", err)
+            cause(err)
+        }
+        Csv(err: csv::Error) {
+            from()
+            display("Could not serialize to CSV: {}", err)
+            cause(err)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    JsonLines,
+    Csv
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Option<ExportFormat> {
+        match format {
+            "jsonl" => Some(ExportFormat::JsonLines),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Csv => "csv"
+        }
+    }
+}
+
+/// Identifies the analyzed crate on every exported row.
+#[derive(Serialize)]
+struct CrateKey<'a> {
+    crate_name: &'a str,
+    crate_version: &'a str
+}
+
+#[derive(Serialize)]
+struct Record<'a, T> {
+    #[serde(flatten)]
+    key: &'a CrateKey<'a>,
+    #[serde(flatten)]
+    row: &'a T
+}
+
+/// A dependency function and whether the analyzed crate reaches it.
+#[derive(Serialize)]
+struct FunctionRow<'a> {
+    dep_name: &'a str,
+    dep_version: &'a str,
+    func: &'a str,
+    #[serde(rename = "has_LOC")]
+    has_loc: bool,
+    is_public: bool,
+    used: bool,
+    depth: Option<usize>
+}
+
+enum Sink {
+    JsonLines(BufWriter<File>),
+    Csv(Box<csv::Writer<File>>)
+}
+
+impl Sink {
+    fn create(path: &Path, format: ExportFormat) -> Result<Sink, ExportError> {
+        Ok(match format {
+            ExportFormat::JsonLines => Sink::JsonLines(BufWriter::new(File::create(path)?)),
+            ExportFormat::Csv => Sink::Csv(Box::new(csv::Writer::from_path(path)?))
+        })
+    }
+
+    fn write<T: Serialize>(&mut self, key: &CrateKey, row: &T) -> Result<(), ExportError> {
+        match self {
+            Sink::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, &Record{ key, row })?;
+                writer.write_all(b"\n")?;
+            },
+            // The csv crate can't flatten, but it writes a tuple of structs as
+            // one record with the fields of both.
+            Sink::Csv(writer) => writer.serialize((key, row))?
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ExportError> {
+        match self {
+            Sink::JsonLines(writer) => writer.flush()?,
+            Sink::Csv(writer) => writer.flush()?
+        }
+        Ok(())
+    }
+}
+
+/// Writes metrics into `metrics`, `dep_metrics`, `dep_path_metrics`, `bloat`
+/// and `functions` files of the chosen format, one row per crate, dependency,
+/// module or impl block and dependency function.
+pub struct Exporter {
+    metrics: Sink,
+    dep_metrics: Sink,
+    dep_path_metrics: Sink,
+    bloat: Sink,
+    functions: Sink
+}
+
+impl Exporter {
+    pub fn create(directory: &Path, format: ExportFormat) -> Result<Exporter, ExportError> {
+        create_dir_all(directory)?;
+        let file = |name: &str| directory.join(format!("{}.{}", name, format.extension()));
+        Ok(Exporter {
+            metrics: Sink::create(&file("metrics"), format)?,
+            dep_metrics: Sink::create(&file("dep_metrics"), format)?,
+            dep_path_metrics: Sink::create(&file("dep_path_metrics"), format)?,
+            bloat: Sink::create(&file("bloat"), format)?,
+            functions: Sink::create(&file("functions"), format)?
+        })
+    }
+
+    pub fn write(&mut self, metrics: &Metrics, crate_name: &str, crate_version: &str) -> Result<(), ExportError> {
+        let key = CrateKey{ crate_name, crate_version };
+        self.metrics.write(&key, metrics)?;
+        for dep_metric in &metrics.depMetrics {
+            self.dep_metrics.write(&key, dep_metric)?;
+        }
+        for path_metric in &metrics.path_metrics {
+            self.dep_path_metrics.write(&key, path_metric)?;
+        }
+        for bloat in &metrics.bloat {
+            self.bloat.write(&key, bloat)?;
+        }
+
+        let lists = metrics.used_funcs.iter().map(|f| (f, true))
+            .chain(metrics.unused_funcs.iter().map(|f| (f, false)));
+        for ((dep_name, dep_version, funcs), used) in lists {
+            for func in funcs {
+                self.functions.write(&key, &FunctionRow{
+                    dep_name,
+                    dep_version,
+                    func: &func.def_id,
+                    has_loc: func.has_loc,
+                    is_public: func.is_externally_visible,
+                    used,
+                    depth: func.depth
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), ExportError> {
+        self.metrics.flush()?;
+        self.dep_metrics.flush()?;
+        self.dep_path_metrics.flush()?;
+        self.bloat.flush()?;
+        self.functions.flush()
+    }
+}
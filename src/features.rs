@@ -0,0 +1,304 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::read_to_string;
+use std::io;
+use std::path::Path;
+use toml::Value;
+
+use crate::def_path::crate_name_of;
+use crate::index_calculator::{Metrics, PathKind};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ManifestError {
+        Io(err: io::Error) {
+            from()
+            display("Could not read Cargo.toml: {}", err)
+            cause(err)
+        }
+        Malformed(err: toml::de::Error) {
+            from()
+            display("Malformed Cargo.toml: {}", err)
+            cause(err)
+        }
+    }
+}
+
+/// A normal dependency declared in `Cargo.toml`. `key` is the name features
+/// refer to it by, which differs from the package name for renamed
+/// dependencies.
+pub struct DeclaredDependency {
+    pub key: String,
+    pub package: String,
+    pub optional: bool
+}
+
+/// The parts of a crate's `Cargo.toml` that decide which dependencies are built.
+pub struct Manifest {
+    pub dependencies: Vec<DeclaredDependency>,
+    pub features: BTreeMap<String, Vec<String>>
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, ManifestError> {
+        let value: Value = read_to_string(path)?.parse()?;
+        Ok(Manifest::from_value(&value))
+    }
+
+    fn from_value(value: &Value) -> Manifest {
+        let mut dependencies = Vec::new();
+        read_dependencies(value.get("dependencies"), &mut dependencies);
+        // Dev and build dependencies never end up in the callgraph, target
+        // specific ones do.
+        if let Some(targets) = value.get("target").and_then(Value::as_table) {
+            for target in targets.values() {
+                read_dependencies(target.get("dependencies"), &mut dependencies);
+            }
+        }
+
+        let mut features = BTreeMap::new();
+        if let Some(table) = value.get("features").and_then(Value::as_table) {
+            for (name, enables) in table {
+                let enables = enables.as_array()
+                    .map(|list| list.iter().filter_map(Value::as_str).map(String::from).collect())
+                    .unwrap_or_default();
+                features.insert(name.to_string(), enables);
+            }
+        }
+
+        Manifest { dependencies, features }
+    }
+
+    /// The dependency the lockfile lists as `package`.
+    pub fn dependency(&self, package: &str) -> Option<&DeclaredDependency> {
+        self.dependencies.iter().find(|d| d.package == package)
+    }
+
+    /// Features that switch on the optional dependency `key`, either by
+    /// naming it or one of its features.
+    pub fn features_enabling(&self, key: &str) -> Vec<String> {
+        let feature_prefix = format!("{}/", key);
+        self.features.iter()
+            .filter(|(_, enables)| enables.iter().any(|e| e == key || e.starts_with(&feature_prefix)))
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+}
+
+fn read_dependencies(table: Option<&Value>, dependencies: &mut Vec<DeclaredDependency>) {
+    let table = match table.and_then(Value::as_table) {
+        None => return,
+        Some(table) => table
+    };
+    for (key, spec) in table {
+        dependencies.push(DeclaredDependency {
+            key: key.to_string(),
+            package: spec.get("package").and_then(Value::as_str).unwrap_or(key).to_string(),
+            optional: spec.get("optional").and_then(Value::as_bool).unwrap_or(false)
+        });
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    /// An optional dependency none of whose functions are used.
+    DisableOptional,
+    /// A required dependency none of whose functions are used.
+    Unused,
+    /// Features of a used dependency that only turn on one of its own
+    /// optional dependencies the crate never reaches.
+    DisableFeature
+}
+
+#[derive(Serialize)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub dependency: String,
+    pub version: String,
+    /// The optional dependency of `dependency` that `DisableFeature` turns off.
+    pub optional_dependency: Option<String>,
+    /// Functions and LOC that would no longer be built.
+    pub functions: usize,
+    pub loc: usize,
+    /// Features that turn the dependency on, of the analyzed crate or, for
+    /// `DisableFeature`, of `dependency`.
+    pub features: Vec<String>
+}
+
+/// Suggests dependencies and dependency features the analyzed crate could turn
+/// off. Entirely unused dependencies are checked against `manifest`, the
+/// optional dependencies of used ones against their own manifest in
+/// `dep_manifests`, keyed by name and version. Dependencies without functions
+/// in the callgraph, like proc macros, are left out.
+pub fn suggest(metrics: &Metrics, manifest: &Manifest, dep_manifests: &HashMap<(String, String), Manifest>) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    for dep in &metrics.depMetrics {
+        if dep.totalCount == 0 {
+            continue;
+        }
+        if dep.usedCount > 0 {
+            if let Some(dep_manifest) = dep_manifests.get(&(dep.crate_name.to_string(), dep.crate_version.to_string())) {
+                suggestions.append(&mut suggest_features(metrics, &dep.crate_name, &dep.crate_version, dep_manifest));
+            }
+            continue;
+        }
+        let declared = match manifest.dependency(&dep.crate_name) {
+            // Dev and build dependencies still show up in the lockfile.
+            None => continue,
+            Some(declared) => declared
+        };
+        let (kind, features) = if declared.optional {
+            (SuggestionKind::DisableOptional, manifest.features_enabling(&declared.key))
+        } else {
+            (SuggestionKind::Unused, Vec::new())
+        };
+        suggestions.push(Suggestion {
+            kind,
+            dependency: dep.crate_name.to_string(),
+            version: dep.crate_version.to_string(),
+            optional_dependency: None,
+            functions: dep.totalCount,
+            loc: dep.total_loc,
+            features
+        });
+    }
+    suggestions
+}
+
+/// Optional dependencies of the used dependency `name` whose functions the
+/// crate reaches through none of its dependencies, with the features of
+/// `name` that switch them on.
+fn suggest_features(metrics: &Metrics, name: &str, version: &str, dep_manifest: &Manifest) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    for optional in dep_manifest.dependencies.iter().filter(|d| d.optional) {
+        let crate_name = crate_name_of(&optional.package);
+        // Module paths start with the crate name and cover every function once.
+        let modules: Vec<_> = metrics.path_metrics.iter()
+            .filter(|p| p.kind == PathKind::Module && p.path.split("::").next() == Some(crate_name.as_str()))
+            .collect();
+        if modules.iter().any(|p| p.used_count > 0) {
+            continue;
+        }
+        let built: Vec<_> = modules.iter().filter(|p| p.dep_name == name && p.dep_version == version).collect();
+        let features = dep_manifest.features_enabling(&optional.key);
+        if built.is_empty() || features.is_empty() {
+            continue;
+        }
+        suggestions.push(Suggestion {
+            kind: SuggestionKind::DisableFeature,
+            dependency: name.to_string(),
+            version: version.to_string(),
+            optional_dependency: Some(optional.package.to_string()),
+            functions: built.iter().map(|p| p.total_count).sum(),
+            loc: built.iter().map(|p| p.total_loc).sum(),
+            features
+        });
+    }
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_calculator::{DepMetric, PathMetric};
+
+    fn manifest(toml: &str) -> Manifest {
+        Manifest::from_value(&toml.parse().unwrap())
+    }
+
+    fn dep(name: &str, used: usize, total: usize) -> DepMetric {
+        DepMetric {
+            crate_name: name.to_string(),
+            crate_version: "1.0.0".to_string(),
+            usedCount: used,
+            totalCount: total,
+            total_loc: 10 * total,
+            used_loc: 10 * used,
+            total_count_with_LOC: total,
+            used_count_with_LOC: used
+        }
+    }
+
+    fn module(dep_name: &str, path: &str, used: usize, total: usize) -> PathMetric {
+        PathMetric {
+            dep_name: dep_name.to_string(),
+            dep_version: "1.0.0".to_string(),
+            kind: PathKind::Module,
+            path: path.to_string(),
+            used_count: used,
+            total_count: total,
+            used_loc: 10 * used,
+            total_loc: 10 * total
+        }
+    }
+
+    fn metrics(deps: Vec<DepMetric>, path_metrics: Vec<PathMetric>) -> Metrics {
+        Metrics { depMetrics: deps, path_metrics, ..Default::default() }
+    }
+
+    const MANIFEST: &str = r#"
+        [dependencies]
+        required = "1"
+        optional = { version = "1", optional = true }
+        serde_derive = "1"
+
+        [dev-dependencies]
+        dev-only = "1"
+
+        [features]
+        default = ["optional"]
+        extra = ["optional/std"]
+    "#;
+
+    #[test]
+    fn suggests_removing_unused_required_dependencies() {
+        let suggestions = suggest(&metrics(vec![dep("required", 0, 4)], Vec::new()), &manifest(MANIFEST), &HashMap::new());
+        assert_eq!(suggestions.len(), 1);
+        assert!(matches!(suggestions[0].kind, SuggestionKind::Unused));
+        assert_eq!((suggestions[0].functions, suggestions[0].loc), (4, 40));
+        assert!(suggestions[0].features.is_empty());
+    }
+
+    #[test]
+    fn suggests_features_turning_on_unused_optional_dependencies() {
+        let suggestions = suggest(&metrics(vec![dep("optional", 0, 2)], Vec::new()), &manifest(MANIFEST), &HashMap::new());
+        assert_eq!(suggestions.len(), 1);
+        assert!(matches!(suggestions[0].kind, SuggestionKind::DisableOptional));
+        assert_eq!(suggestions[0].features, vec!["default", "extra"]);
+    }
+
+    #[test]
+    fn skips_dependencies_that_are_not_built() {
+        let deps = vec![dep("dev-only", 0, 3), dep("serde_derive", 0, 0), dep("required", 1, 4)];
+        assert!(suggest(&metrics(deps, Vec::new()), &manifest(MANIFEST), &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn suggests_features_of_used_dependencies() {
+        let dep_manifest = manifest(r#"
+            [dependencies]
+            used-opt = { version = "1", optional = true }
+            unused-opt = { version = "1", optional = true }
+
+            [features]
+            std = ["used-opt"]
+            fancy = ["unused-opt/alloc"]
+        "#);
+        let paths = vec![
+            module("required", "required", 1, 2),
+            module("required", "used_opt", 1, 1),
+            module("required", "unused_opt", 0, 2),
+            module("required", "unused_opt::inner", 0, 1)
+        ];
+        let mut dep_manifests = HashMap::new();
+        dep_manifests.insert(("required".to_string(), "1.0.0".to_string()), dep_manifest);
+
+        let suggestions = suggest(&metrics(vec![dep("required", 2, 6)], paths), &manifest(MANIFEST), &dep_manifests);
+        assert_eq!(suggestions.len(), 1);
+        assert!(matches!(suggestions[0].kind, SuggestionKind::DisableFeature));
+        assert_eq!(suggestions[0].optional_dependency.as_deref(), Some("unused-opt"));
+        assert_eq!(suggestions[0].features, vec!["fancy"]);
+        assert_eq!((suggestions[0].functions, suggestions[0].loc), (3, 30));
+    }
+}
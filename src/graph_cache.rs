@@ -0,0 +1,312 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::callgraph::NormalizeError;
+use crate::compact_graph::{CompactGraph, CompactGraphBuilder, Symbol};
+
+const MAGIC: &[u8; 4] = b"CAGC";
+/// Bumped whenever the layout below changes, older caches are rebuilt.
+const FORMAT_VERSION: u32 = 2;
+/// Magic, format version, source hash, function count and call count.
+const HEADER_SIZE: usize = 4 + 4 + 8 + 4 + 4;
+/// The fewest bytes a function and a call take: three symbols, the def id
+/// length, lines, visibility and call offset, then target and flag.
+const MIN_FUNCTION_SIZE: usize = 4 * 3 + 4 + 8 + 1 + 4;
+const MIN_CALL_SIZE: usize = 4 + 1;
+
+/// Name of the cache file next to the other cached data of a crate version.
+pub const CACHE_FILE: &str = "callgraph.bin";
+
+/// FNV-1a over the source file, stored in the cache to notice when the
+/// callgraph it was built from changes. The file is read in chunks.
+pub fn source_hash<R: Read>(mut reader: R) -> io::Result<u64> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(hash),
+            Ok(count) => count,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        };
+        for byte in &buffer[..count] {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// Writes a graph as a header with its size, a string table, a node table
+/// referring to it and the calls as CSR arrays: per node the offset of its
+/// first call, then the targets and flags of all calls. Callers are rebuilt
+/// when loading. Numbers are little endian, strings are length prefixed.
+pub fn write_cache(path: &Path, graph: &CompactGraph, hash: u64) -> io::Result<()> {
+    // Write next to the target and rename, so an interrupted run never
+    // leaves a truncated cache behind.
+    let partial = path.with_extension("bin.partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    out.write_all(MAGIC)?;
+    put_u32(&mut out, FORMAT_VERSION)?;
+    put_u64(&mut out, hash)?;
+    put_u32(&mut out, graph.len() as u32)?;
+    put_u32(&mut out, graph.call_count() as u32)?;
+    put_u32(&mut out, graph.strings().len() as u32)?;
+    for s in graph.strings().iter() {
+        put_str(&mut out, s)?;
+    }
+
+    for n in graph.nodes() {
+        put_u32(&mut out, optional_id(graph.package_symbol(n)))?;
+        put_u32(&mut out, optional_id(graph.version_symbol(n)))?;
+        put_u32(&mut out, graph.crate_symbol(n))?;
+        put_str(&mut out, graph.def_id(n))?;
+        put_u64(&mut out, graph.num_lines(n) as i64 as u64)?;
+        out.write_all(&[graph.is_externally_visible(n) as u8])?;
+    }
+
+    let mut offset = 0;
+    for n in graph.nodes() {
+        put_u32(&mut out, offset)?;
+        offset += graph.callees(n).len() as u32;
+    }
+    put_u32(&mut out, offset)?;
+    for n in graph.nodes() {
+        for target in graph.callees(n) {
+            put_u32(&mut out, *target)?;
+        }
+    }
+    for n in graph.nodes() {
+        for flag in graph.call_flags(n) {
+            out.write_all(&[*flag as u8])?;
+        }
+    }
+    out.flush()?;
+    drop(out);
+    fs::rename(&partial, path)
+}
+
+/// Loads a cached graph. Returns `Ok(None)` if the cache has another format
+/// version or was built from a different source. Graphs with more functions
+/// and calls than `max_size` are rejected from the header alone.
+pub fn read_cache(path: &Path, hash: u64, max_size: Option<usize>) -> Result<Option<CompactGraph>, NormalizeError> {
+    let mut file = File::open(path)?;
+    let mut header = [0; HEADER_SIZE];
+    file.read_exact(&mut header).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => invalid("callgraph cache is truncated"),
+        _ => err
+    })?;
+    let mut reader = Reader{ bytes: &header, position: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err(invalid("not a callgraph cache").into());
+    }
+    if reader.u32()? != FORMAT_VERSION || reader.u64()? != hash {
+        return Ok(None);
+    }
+    let node_count = reader.u32()? as usize;
+    let call_count = reader.u32()? as usize;
+    if let Some(limit) = max_size {
+        if node_count + call_count > limit {
+            return Err(NormalizeError::TooLarge(limit));
+        }
+    }
+    // Counts the file can't hold would otherwise only fail after allocating.
+    let length = file.metadata()?.len() as usize;
+    if length < HEADER_SIZE + node_count * MIN_FUNCTION_SIZE + 4 + call_count * MIN_CALL_SIZE {
+        return Err(invalid("callgraph cache is truncated").into());
+    }
+
+    let mut bytes = Vec::with_capacity(length - HEADER_SIZE);
+    file.read_to_end(&mut bytes)?;
+    let mut reader = Reader{ bytes: &bytes, position: 0 };
+    let string_count = reader.u32()? as usize;
+    let mut strings = Vec::new();
+    for _ in 0..string_count {
+        strings.push(reader.string()?);
+    }
+    let string = |id: u32| strings.get(id as usize).map(String::as_str).ok_or_else(|| invalid("string id out of range"));
+    let optional = |id: u32| if id == 0 { Ok(None) } else { string(id - 1).map(Some) };
+
+    let mut builder = CompactGraphBuilder::default();
+    for _ in 0..node_count {
+        let package = optional(reader.u32()?)?;
+        let version = optional(reader.u32()?)?;
+        let crate_name = string(reader.u32()?)?;
+        let def_id = reader.string()?;
+        let num_lines = reader.u64()? as i64 as isize;
+        let is_externally_visible = reader.take(1)?[0] != 0;
+        builder.add_function(package, version, crate_name, &def_id, num_lines, is_externally_visible)?;
+    }
+
+    let mut offsets = Vec::with_capacity(node_count + 1);
+    for _ in 0..=node_count {
+        offsets.push(reader.u32()? as usize);
+    }
+    let edge_count = offsets[node_count];
+    if edge_count != call_count {
+        return Err(invalid("call count does not match the header").into());
+    }
+    let mut targets = Vec::with_capacity(edge_count);
+    for _ in 0..edge_count {
+        targets.push(reader.u32()? as usize);
+    }
+    let flags = reader.take(edge_count)?;
+
+    for source in 0..node_count {
+        let (start, end) = (offsets[source], offsets[source + 1]);
+        if start > end || end > edge_count {
+            return Err(invalid("call offsets out of order").into());
+        }
+        for edge in start..end {
+            if targets[edge] >= node_count {
+                return Err(invalid("call target out of range").into());
+            }
+            builder.add_call(source, targets[edge], flags[edge] != 0)?;
+        }
+    }
+
+    Ok(Some(builder.build()))
+}
+
+/// 0 for a missing value, otherwise the symbol shifted by one.
+fn optional_id(symbol: Option<Symbol>) -> u32 {
+    symbol.map_or(0, |s| s + 1)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn put_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn put_u64<W: Write>(out: &mut W, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn put_str<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    put_u32(out, value.len() as u32)?;
+    out.write_all(value.as_bytes())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let end = self.position.checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid("callgraph cache is truncated"))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| invalid("string is not UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn graph() -> CompactGraph {
+        let mut builder = CompactGraphBuilder::default();
+        builder.add_function(Some("app"), Some("0.1.0"), "app", "app[1111]::main[0]", 12, false).unwrap();
+        builder.add_function(Some("dep-a"), Some("1.0.0"), "dep_a", "dep_a[2222]::run[0]", -1, true).unwrap();
+        builder.add_function(None, None, "core", "core[3333]::fmt[0]::write[0]", 40, true).unwrap();
+        builder.add_call(0, 1, false).unwrap();
+        builder.add_call(0, 2, true).unwrap();
+        builder.add_call(1, 2, false).unwrap();
+        builder.build()
+    }
+
+    /// A cache path of its own per test, tests run in parallel.
+    fn cache_path(test: &str) -> PathBuf {
+        env::temp_dir().join(format!("graph_cache_{}_{}.bin", process::id(), test))
+    }
+
+    #[test]
+    fn hashes_with_fnv_1a() {
+        assert_eq!(source_hash(&b""[..]).unwrap(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(source_hash(&b"a"[..]).unwrap(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let path = cache_path("round_trip");
+        let graph = graph();
+        write_cache(&path, &graph, 42).unwrap();
+        let read = read_cache(&path, 42, Some(6)).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.len(), graph.len());
+        assert_eq!(read.call_count(), graph.call_count());
+        for n in graph.nodes() {
+            assert_eq!(read.package(n), graph.package(n));
+            assert_eq!(read.version(n), graph.version(n));
+            assert_eq!(read.crate_name(n), graph.crate_name(n));
+            assert_eq!(read.def_id(n), graph.def_id(n));
+            assert_eq!(read.num_lines(n), graph.num_lines(n));
+            assert_eq!(read.is_externally_visible(n), graph.is_externally_visible(n));
+            assert_eq!(read.callees(n), graph.callees(n));
+            assert_eq!(read.call_flags(n), graph.call_flags(n));
+            assert_eq!(read.callers(n), graph.callers(n));
+        }
+    }
+
+    #[test]
+    fn ignores_caches_of_other_sources() {
+        let path = cache_path("hash_mismatch");
+        write_cache(&path, &graph(), 42).unwrap();
+        let read = read_cache(&path, 43, None);
+        fs::remove_file(&path).unwrap();
+        assert!(read.unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_caches() {
+        let path = cache_path("truncated");
+        write_cache(&path, &graph(), 42).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        let read = read_cache(&path, 42, None);
+        fs::remove_file(&path).unwrap();
+        match read {
+            Err(NormalizeError::Io(err)) => assert_eq!(err.kind(), ErrorKind::InvalidData),
+            other => panic!("unexpected result: {:?}", other.map(|g| g.map(|g| g.len())))
+        }
+    }
+
+    #[test]
+    fn rejects_large_graphs_from_the_header() {
+        let path = cache_path("too_large");
+        write_cache(&path, &graph(), 42).unwrap();
+        // Cut off after the header, a full read would fail on the rest.
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..HEADER_SIZE]).unwrap();
+        let read = read_cache(&path, 42, Some(5));
+        fs::remove_file(&path).unwrap();
+        match read {
+            Err(NormalizeError::TooLarge(5)) => (),
+            other => panic!("unexpected result: {:?}", other.map(|g| g.map(|g| g.len())))
+        }
+    }
+}
@@ -1,18 +1,18 @@
-use serde_json::{to_string_pretty, from_reader};
 use serde::{Serialize, Deserialize};
-use std::fs::{File, write, copy};
-use std::path::PathBuf;
-use std::io::{BufReader};
-use std::collections::{HashMap, HashSet};
-use std::process::Command;
-use std::str;
+use std::fs::{File, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::io::{self, BufReader};
+use std::collections::{HashMap, HashSet, VecDeque};
 use cargo_lock::Lockfile;
+use crate::callgraph::{stream_callgraph, stream_legacy_callgraph, NormalizeError};
+use crate::compact_graph::{CompactGraph, Symbol};
+use crate::def_path::DefPath;
+use crate::graph_cache;
+use crate::errors::AnalysisError;
 use semver::{Version};
-use std::error;
-use std::{thread, time};
 
 
-type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
+type Result<T> = std::result::Result<T, AnalysisError>;
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Node {
@@ -21,50 +21,176 @@ pub struct Node {
     pub package_version: Option<String>,
     pub crate_name: String,
     pub relative_def_id: String,
+    #[serde(default)]
     pub inward_edges: Vec<Edge>,
+    #[serde(default)]
     pub outward_edges: Vec<Edge>,
     pub num_lines: isize,
-    pub is_externally_visible: bool,
-    pub node_type: Option<String>
+    pub is_externally_visible: bool
+}
+
+/// How a classification pass reached a node. Passes keep one label per node
+/// in a separate array indexed by node id, `None` for nodes they never reach.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NodeClass {
+    /// Code of the package being analyzed, or of its closure in the
+    /// per-dependency pass.
+    LocalFunc,
+    /// Dependency code reached from the package's own code.
+    UsedDepFunc,
+    /// Code of the dependency reached from the analyzed crate, in the
+    /// per-dependency pass.
+    LocalFuncPub,
+    /// Code of the dependency's own dependencies reached from the analyzed
+    /// crate, in the per-dependency pass.
+    UsedDepFuncPub,
+    StdFunc
 }
 
+type Labels = Vec<Option<NodeClass>>;
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Edge {
     pub target: usize,
     pub some_bool: bool
 }
 
+/// A function of a dependency's closure as stored in `dep_func_metrics`.
+#[derive(Clone)]
+pub struct DepFunc {
+    pub def_id: String,
+    pub has_loc: bool,
+    pub is_externally_visible: bool,
+    /// Calls from the nearest function of the analyzed crate, `None` if the
+    /// crate never reaches it.
+    pub depth: Option<usize>
+}
+
+impl DepFunc {
+    fn from_node(graph: &CompactGraph, node: usize, depths: &[Option<usize>]) -> DepFunc {
+        DepFunc {
+            def_id: graph.def_id(node).to_string(),
+            has_loc: graph.num_lines(node) > 0,
+            is_externally_visible: graph.is_externally_visible(node),
+            depth: depths[node]
+        }
+    }
+}
+
+/// Unused dependency code attributed to the direct dependency that pulls it
+/// in. Packages shared with other direct dependencies count towards each of
+/// them, the `exclusive_` fields only cover packages nothing else needs.
+#[derive(Serialize)]
+pub struct DepBloat {
+    pub name: String,
+    pub version: String,
+    /// Packages in the dependency's closure, itself included.
+    pub package_count: usize,
+    pub unused_count: usize,
+    #[serde(rename = "unused_LOC")]
+    pub unused_loc: usize,
+    pub exclusive_unused_count: usize,
+    #[serde(rename = "exclusive_unused_LOC")]
+    pub exclusive_unused_loc: usize
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PathKind {
+    Module,
+    /// An impl block, standing in for the type it implements.
+    Impl
+}
+
+impl PathKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PathKind::Module => "module",
+            PathKind::Impl => "impl"
+        }
+    }
+}
+
+/// Utilization of one module or impl block reached through a direct
+/// dependency. Field names serialize to the column names of the
+/// `dep_path_metrics` table.
+#[derive(Serialize)]
+pub struct PathMetric {
+    pub dep_name: String,
+    pub dep_version: String,
+    pub kind: PathKind,
+    /// Path without disambiguators, e.g. `serde::de` or `serde::de::{impl#3}`.
+    pub path: String,
+    pub used_count: usize,
+    pub total_count: usize,
+    #[serde(rename = "used_LOC")]
+    pub used_loc: usize,
+    #[serde(rename = "total_LOC")]
+    pub total_loc: usize
+}
+
+/// A direct dependency and every package it depends on, itself included.
+struct DepClosure {
+    name: String,
+    version: String,
+    packages: Vec<(String, String)>
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct CrateData {
     pub usedCount: usize,
     pub totalCount: usize
 }
 
+/// Field names serialize to the column names of the `dep_metrics` table.
+#[derive(Serialize)]
 pub struct DepMetric {
+    #[serde(rename = "name")]
     pub crate_name: String,
+    #[serde(rename = "version")]
     pub crate_version: String,
+    #[serde(rename = "used_count")]
     pub usedCount: usize,
+    #[serde(rename = "total_count")]
     pub totalCount: usize,
+    #[serde(rename = "total_LOC")]
     pub total_loc: usize,
+    #[serde(rename = "used_LOC")]
     pub used_loc: usize,
     pub total_count_with_LOC: usize,
     pub used_count_with_LOC: usize
 }
 
+/// Field names serialize to the column names of the `metrics` table.
+#[derive(Serialize, Default)]
 pub struct Metrics {
+    #[serde(rename = "total_func_count")]
     pub TotalFuncCount: usize,
+    #[serde(rename = "local_func_count")]
     pub LocalFuncCount: usize,
+    #[serde(rename = "std_func_count")]
     pub StdFuncCount: usize,
+    #[serde(rename = "total_dep_func_count")]
     pub TotalDepFuncCount: usize,
+    #[serde(rename = "used_dep_func_count")]
     pub UsedDepFuncCount: usize,
+    #[serde(rename = "total_dep_public_func_count")]
     pub TotalDepPublicFuncCount: usize,
+    #[serde(rename = "used_dep_public_func_count")]
     pub UsedDepPublicFuncCount: usize,
+    #[serde(rename = "total_dep_LOC")]
     pub TotalDepLOC: usize,
+    #[serde(rename = "used_dep_LOC")]
     pub UsedDepLOC: usize,
+    #[serde(rename = "total_LOC")]
     pub TotalLOC: usize,
+    #[serde(rename = "local_LOC")]
     pub LocalLOC: usize,
+    #[serde(rename = "total_std_LOC")]
     pub TotalStdLOC: usize,
+    #[serde(rename = "total_public_LOC")]
     pub TotalDepPublicLOC: usize,
+    #[serde(rename = "used_public_LOC")]
     pub UsedDepPublicLOC: usize,
     pub total_func_count_with_LOC: usize,
     pub total_dep_func_count_with_LOC: usize,
@@ -72,9 +198,22 @@ pub struct Metrics {
     pub used_dep_func_count_with_LOC: usize,
     pub total_dep_public_func_count_with_LOC: usize,
     pub used_dep_public_func_count_with_LOC: usize,
-    pub used_funcs: Vec<(String, String, Vec<(String, bool)>)>,
-    pub unused_funcs: Vec<(String, String, Vec<(String, bool)>)>,
-    pub depMetrics: Vec<DepMetric>
+    /// Used dependency functions the crate calls directly.
+    pub direct_dep_func_count: usize,
+    /// Calls from the crate to its deepest and, on average, to its used
+    /// dependency functions.
+    pub max_dep_depth: usize,
+    pub mean_dep_depth: f64,
+    #[serde(skip)]
+    pub used_funcs: Vec<(String, String, Vec<DepFunc>)>,
+    #[serde(skip)]
+    pub unused_funcs: Vec<(String, String, Vec<DepFunc>)>,
+    #[serde(skip)]
+    pub depMetrics: Vec<DepMetric>,
+    #[serde(skip)]
+    pub bloat: Vec<DepBloat>,
+    #[serde(skip)]
+    pub path_metrics: Vec<PathMetric>
 }
 
 // #[derive(Deserialize, Serialize, Clone)]
@@ -84,84 +223,110 @@ pub struct Metrics {
 //     pub nodes_info: Vec<NodeInfo>
 // }
 
-pub fn get_index(callgraph_directory: &PathBuf, update_callgraph_directory: &PathBuf, crate_name: &String, crate_version: &String) -> Result<Metrics>{
-    let deps = get_deps(&callgraph_directory, crate_name, Version::parse(crate_version).unwrap())?;
+/// Computes the metrics of a crate in the Präzi layout, where `callgraph_directory`
+/// holds `callgraph.json` and `Cargo.lock`.
+pub fn get_index(callgraph_directory: &PathBuf, update_callgraph_directory: &PathBuf, crate_name: &String, crate_version: &String, graph_cache: bool, max_graph_size: Option<usize>) -> Result<(Metrics, Option<io::Error>)>{
+    let version = Version::parse(crate_version).map_err(|err| AnalysisError::InvalidVersionFolder(crate_version.to_string(), err))?;
+    let lockfile = load_lockfile(&callgraph_directory.join("Cargo.lock"))?;
+    let (graph, cache_error) = load_crate_graph_dir(callgraph_directory, update_callgraph_directory, graph_cache, max_graph_size)?;
 
-    let callgraph_path = update_with_python(callgraph_directory, update_callgraph_directory)?;    
-    let graph = analyze_graph_for_package(&callgraph_path, crate_name)?;
-    let a = graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string())).count();
+    Ok((compute_metrics(&graph, &lockfile, crate_name, &version)?, cache_error))
+}
+
+/// Computes the metrics of `crate_name` from its callgraph and the lockfile it
+/// was built with. The graph itself is left untouched, every classification
+/// keeps its own labels.
+pub fn compute_metrics(graph: &CompactGraph, lockfile: &Lockfile, crate_name: &str, version: &Version) -> Result<Metrics>{
+    let crate_name = &crate_name.to_string();
+    let deps = get_deps(lockfile, crate_name, version)?;
+
+    let labels = analyze_graph_for_package(graph, crate_name);
+    let (depths, _) = search_from_crate(graph, crate_name);
+
+    // Dependency functions are those of any package but the analyzed one,
+    // used ones are reached from its code.
+    let crate_symbol = graph.symbol(crate_name);
+    let is_dep = |n: &usize| graph.package_symbol(*n).is_some() && graph.package_symbol(*n) != crate_symbol;
+    let is_used_dep = |n: &usize| graph.package_symbol(*n).is_some() && labels[*n] == Some(NodeClass::UsedDepFunc);
+    let is_local = |n: &usize| labels[*n] == Some(NodeClass::LocalFunc);
+    let is_std = |n: &usize| graph.package_symbol(*n).is_none();
+    let has_loc = |n: &usize| graph.num_lines(*n) > 0;
+    let is_public = |n: &usize| graph.is_externally_visible(*n);
+    let loc = |n: usize| graph.loc(n);
+
+    let used_dep_depths: Vec<usize> = graph.nodes()
+        .filter(is_used_dep)
+        .filter_map(|n| depths[n])
+        .collect();
     let mut output = Metrics{
-        TotalFuncCount: graph.iter().count(),
-        total_func_count_with_LOC: graph.iter().filter(|n| n.num_lines > 0).count(),
+        TotalFuncCount: graph.len(),
+        total_func_count_with_LOC: graph.nodes().filter(has_loc).count(),
 
-        LocalFuncCount: graph.iter().filter(|n| n.node_type == Some("local_func".to_string())).count(),
-        local_func_count_with_LOC: graph.iter().filter(|n| n.node_type == Some("local_func".to_string()) && n.num_lines > 0).count(),
+        LocalFuncCount: graph.nodes().filter(is_local).count(),
+        local_func_count_with_LOC: graph.nodes().filter(|n| is_local(n) && has_loc(n)).count(),
 
-        StdFuncCount: graph.iter().filter(|n| n.package_name == None).count(),
+        StdFuncCount: graph.nodes().filter(is_std).count(),
 
-        TotalDepFuncCount: graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string())).count(),
-        total_dep_func_count_with_LOC: graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string()) && n.num_lines > 0).count(),
+        TotalDepFuncCount: graph.nodes().filter(is_dep).count(),
+        total_dep_func_count_with_LOC: graph.nodes().filter(|n| is_dep(n) && has_loc(n)).count(),
 
-        UsedDepFuncCount: graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string())).count(),
-        used_dep_func_count_with_LOC: graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string()) && n.num_lines > 0).count(),
+        UsedDepFuncCount: graph.nodes().filter(is_used_dep).count(),
+        used_dep_func_count_with_LOC: graph.nodes().filter(|n| is_used_dep(n) && has_loc(n)).count(),
 
-        TotalDepPublicFuncCount: graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string()) && n.is_externally_visible).count(),
-        total_dep_public_func_count_with_LOC: graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string()) && n.is_externally_visible && n.num_lines > 0).count(),
-        
-        UsedDepPublicFuncCount: graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string()) && n.is_externally_visible).count(),
-        used_dep_public_func_count_with_LOC: graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string()) && n.is_externally_visible && n.num_lines > 0).count(),
-        
-        TotalDepLOC: graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string())).map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum(),
-        UsedDepLOC: graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string())).map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum(),
-        TotalLOC: graph.iter().map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum(),
-        LocalLOC: graph.iter().filter(|n| n.node_type == Some("local_func".to_string())).map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum(),
-        TotalStdLOC: graph.iter().filter(|n| n.package_name == None).map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum(),
-        TotalDepPublicLOC: graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string()) && n.is_externally_visible).map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum(),
-        UsedDepPublicLOC: graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string()) && n.is_externally_visible).map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum(),
+        TotalDepPublicFuncCount: graph.nodes().filter(|n| is_dep(n) && is_public(n)).count(),
+        total_dep_public_func_count_with_LOC: graph.nodes().filter(|n| is_dep(n) && is_public(n) && has_loc(n)).count(),
+
+        UsedDepPublicFuncCount: graph.nodes().filter(|n| is_used_dep(n) && is_public(n)).count(),
+        used_dep_public_func_count_with_LOC: graph.nodes().filter(|n| is_used_dep(n) && is_public(n) && has_loc(n)).count(),
+
+        TotalDepLOC: graph.nodes().filter(is_dep).map(loc).sum(),
+        UsedDepLOC: graph.nodes().filter(is_used_dep).map(loc).sum(),
+        TotalLOC: graph.nodes().map(loc).sum(),
+        LocalLOC: graph.nodes().filter(is_local).map(loc).sum(),
+        TotalStdLOC: graph.nodes().filter(is_std).map(loc).sum(),
+        TotalDepPublicLOC: graph.nodes().filter(|n| is_dep(n) && is_public(n)).map(loc).sum(),
+        UsedDepPublicLOC: graph.nodes().filter(|n| is_used_dep(n) && is_public(n)).map(loc).sum(),
+        direct_dep_func_count: used_dep_depths.iter().filter(|d| **d == 1).count(),
+        max_dep_depth: used_dep_depths.iter().cloned().max().unwrap_or(0),
+        mean_dep_depth: if used_dep_depths.is_empty() { 0.0 } else { used_dep_depths.iter().sum::<usize>() as f64 / used_dep_depths.len() as f64 },
         used_funcs: Vec::new(),
         unused_funcs: Vec::new(),
-        depMetrics: Vec::new()
+        depMetrics: Vec::new(),
+        bloat: Vec::new(),
+        path_metrics: Vec::new()
     };
-    // let total_count = graph.iter().count();
-    // let total_non_std = graph.iter().filter(|n| n.package_name != None).count();
-    // let non_used_count = graph.iter().filter(|n| n.package_name != None && n.node_type == None).count();
-    // let total_dep_func_count = graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string())).count();
-    // let local_count = graph.iter().filter(|n| n.node_type == Some("local_func".to_string())).count();
-    // let used_dep_count = graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string())).count();
-    // println!("Total func count     = {}", total_non_std);
-    // println!("Local func count     = {}", local_count);
-    // println!("Total dep func count = {}", total_dep_func_count);
-    // println!("Used dep funcs Count = {}", used_dep_count);
-    // println!("Unused dep funcs     = {}", non_used_count);
-    // println!("Own code share       = {}", local_count as f32 / total_non_std as f32);
-    // println!("Leanness index (n)   = {}", used_dep_count as f32 / total_dep_func_count as f32);
-    // let total_dep_func_count_lines: isize = graph.iter().filter(|n| n.package_name != None && &n.package_name != &Some(crate_name.to_string())).map(|n| n.num_lines).sum();
-    // let used_dep_count_lines: isize = graph.iter().filter(|n| n.package_name != None && n.node_type == Some("used_dep_func".to_string())).map(|n| n.num_lines).sum();
-    // println!("Leanness index (l)   = {}", used_dep_count_lines as f32 / total_dep_func_count_lines as f32);
-    // println!("Dependency index     = {}", total_dep_func_count as f32 / total_count as f32);
 
+    let mut closures: Vec<DepClosure> = Vec::new();
     for n in deps{
-        let tr_deps = get_all_deps(&callgraph_directory, &n.0, Version::parse(&n.1).unwrap())?;
-        let dep_graph = analyze_graph_for_package2(&callgraph_path, &n.0, crate_name, &tr_deps);
-
-        let used_nodes: Vec<(String, bool)> = dep_graph.iter()
-            .filter(|n| n.node_type == Some("local_func_pub".to_string()) || n.node_type == Some("used_dep_func_pub".to_string()))
-            .map(|n| (n.relative_def_id.to_string(), n.num_lines > 0)).collect();
-        let unused_nodes: Vec<(String, bool)> = dep_graph.iter()
-            .filter(|n| n.node_type != None && n.node_type != Some("std_func".to_string()) && n.node_type != Some("local_func_pub".to_string()) && n.node_type != Some("used_dep_func_pub".to_string()))
-            .map(|n| (n.relative_def_id.to_string(), n.num_lines > 0)).collect();   
-
-        let total = dep_graph.iter().filter(|n| n.node_type != None && n.node_type != Some("std_func".to_string())).count();
-        let total_used = dep_graph.iter().filter(|n| n.node_type == Some("local_func_pub".to_string()) || n.node_type == Some("used_dep_func_pub".to_string())).count();
+        let tr_deps = get_all_deps(lockfile, &n.0, &n.1)
+            .map_err(|err| AnalysisError::Classify(n.0.to_string(), Box::new(err)))?;
+        let dep_labels = analyze_graph_for_package2(graph, &n.0, crate_name, &tr_deps);
+        let mut packages = tr_deps.clone();
+        packages.push((n.0.to_string(), n.1.to_string()));
+        closures.push(DepClosure{ name: n.0.to_string(), version: n.1.to_string(), packages });
+
+        // Functions of the dependency's closure, and those reached from the
+        // analyzed crate.
+        let counted = |node: &usize| !matches!(dep_labels[*node], None | Some(NodeClass::StdFunc));
+        let used = |node: &usize| matches!(dep_labels[*node], Some(NodeClass::LocalFuncPub) | Some(NodeClass::UsedDepFuncPub));
+
+        let used_nodes: Vec<DepFunc> = graph.nodes()
+            .filter(used)
+            .map(|n| DepFunc::from_node(graph, n, &depths)).collect();
+        let unused_nodes: Vec<DepFunc> = graph.nodes()
+            .filter(|n| counted(n) && !used(n))
+            .map(|n| DepFunc::from_node(graph, n, &depths)).collect();
+
+        let total = graph.nodes().filter(counted).count();
+        let total_used = graph.nodes().filter(used).count();
         
-        let total_withLOC = dep_graph.iter().filter(|n| n.node_type != None && n.node_type != Some("std_func".to_string()) && n.num_lines > 0).count();
-        let total_used_withLOC = dep_graph.iter().filter(|n| (n.node_type == Some("local_func_pub".to_string()) || n.node_type == Some("used_dep_func_pub".to_string())) && n.num_lines > 0).count();
+        let total_withLOC = graph.nodes().filter(|n| counted(n) && has_loc(n)).count();
+        let total_used_withLOC = graph.nodes().filter(|n| used(n) && has_loc(n)).count();
         
-        let total_loc =     dep_graph.iter().filter(|n| n.node_type != None && n.node_type != Some("std_func".to_string())).map(|n| if n.num_lines >= 0 { n.num_lines } else { 0 } as usize).sum();
-        let used_loc = dep_graph.iter().filter(|n| n.node_type == Some("local_func_pub".to_string()) || n.node_type == Some("used_dep_func_pub".to_string())).map(|node| {
-            if node.num_lines >= 0 {  return node.num_lines  as usize} else { return 0 as usize} }
-        ).sum();
+        let total_loc = graph.nodes().filter(counted).map(loc).sum();
+        let used_loc = graph.nodes().filter(used).map(loc).sum();
     
+        output.path_metrics.append(&mut roll_up_paths(graph, &n.0, &n.1.to_string(), counted, used));
         output.used_funcs.push((n.0.to_string(), n.1.to_string(), used_nodes));
         output.unused_funcs.push((n.0.to_string(), n.1.to_string(), unused_nodes));
 
@@ -181,110 +346,242 @@ pub fn get_index(callgraph_directory: &PathBuf, update_callgraph_directory: &Pat
         )
     }
 
+    output.bloat = attribute_bloat(graph, &labels, crate_name, &closures);
+
     Ok(output)
 }
 
-fn analyze_graph_for_package(callgraph_path: &PathBuf, crate_name: &String) -> Result<Vec<Node>>{
-    let mut dep_graph = get_call_graph(callgraph_path)?;
-    let mut node_index: usize = 0;
-    while dep_graph.len() > node_index{
-        let node = dep_graph.get(node_index).unwrap();
-        
-        if &node.package_name == &Some(crate_name.to_string()){
-            traverse_node_downwards(&mut dep_graph, node_index.clone(), crate_name, false);
+/// Sums the functions a per-dependency pass counts per module and impl block.
+fn roll_up_paths<C, U>(graph: &CompactGraph, dep_name: &str, dep_version: &str, counted: C, used: U) -> Vec<PathMetric>
+    where C: Fn(&usize) -> bool, U: Fn(&usize) -> bool{
+    let mut paths: Vec<PathMetric> = Vec::new();
+    let mut positions: HashMap<(PathKind, String), usize> = HashMap::new();
+    for n in graph.nodes().filter(&counted){
+        let def_path = match DefPath::parse(graph.def_id(n)){
+            None => continue,
+            Some(def_path) => def_path
+        };
+        let is_used = used(&n);
+        let loc = graph.loc(n);
+
+        let mut keys = vec![(PathKind::Module, def_path.module_path())];
+        if let Some(impl_path) = def_path.impl_path(){
+            keys.push((PathKind::Impl, impl_path));
+        }
+        for (kind, path) in keys{
+            let position = *positions.entry((kind, path.to_string())).or_insert_with(|| {
+                paths.push(PathMetric{
+                    dep_name: dep_name.to_string(),
+                    dep_version: dep_version.to_string(),
+                    kind,
+                    path,
+                    used_count: 0,
+                    total_count: 0,
+                    used_loc: 0,
+                    total_loc: 0
+                });
+                paths.len() - 1
+            });
+            let metric = &mut paths[position];
+            metric.total_count += 1;
+            metric.total_loc += loc;
+            if is_used{
+                metric.used_count += 1;
+                metric.used_loc += loc;
+            }
         }
+    }
+
+    paths.sort_by(|a, b| (a.kind, &a.path).cmp(&(b.kind, &b.path)));
+    paths
+}
 
-        node_index += 1;
+/// Sums the dependency functions the crate does not reach per package and
+/// hands them to every direct dependency whose closure contains the package.
+fn attribute_bloat(graph: &CompactGraph, labels: &Labels, crate_name: &str, closures: &[DepClosure]) -> Vec<DepBloat>{
+    let crate_symbol = graph.symbol(crate_name);
+    let mut unused: HashMap<(Symbol, Symbol), (usize, usize)> = HashMap::new();
+    for n in graph.nodes(){
+        if let (Some(package), Some(version)) = (graph.package_symbol(n), graph.version_symbol(n)){
+            if Some(package) != crate_symbol && labels[n] != Some(NodeClass::UsedDepFunc){
+                let entry = unused.entry((package, version)).or_insert((0, 0));
+                entry.0 += 1;
+                entry.1 += graph.loc(n);
+            }
+        }
     }
+    // Packages without functions in the graph have nothing to attribute.
+    let unused_of = |package: &(String, String)| match (graph.symbol(&package.0), graph.symbol(&package.1)){
+        (Some(name), Some(version)) => unused.get(&(name, version)).cloned().unwrap_or((0, 0)),
+        _ => (0, 0)
+    };
 
-    Ok(dep_graph)
+    let mut introduced_by: HashMap<&(String, String), usize> = HashMap::new();
+    for closure in closures{
+        for package in &closure.packages{
+            *introduced_by.entry(package).or_insert(0) += 1;
+        }
+    }
+
+    closures.iter().map(|closure| {
+        let mut bloat = DepBloat{
+            name: closure.name.to_string(),
+            version: closure.version.to_string(),
+            package_count: closure.packages.len(),
+            unused_count: 0,
+            unused_loc: 0,
+            exclusive_unused_count: 0,
+            exclusive_unused_loc: 0
+        };
+        for package in &closure.packages{
+            let (count, loc) = unused_of(package);
+            bloat.unused_count += count;
+            bloat.unused_loc += loc;
+            if introduced_by[package] == 1{
+                bloat.exclusive_unused_count += count;
+                bloat.exclusive_unused_loc += loc;
+            }
+        }
+        bloat
+    }).collect()
 }
 
-fn analyze_graph_for_package2(callgraph_path: &PathBuf, crate_name: &String, main_package: &String, deps: &Vec<(String, String)>) -> Vec<Node>{
-    let mut dep_graph = get_call_graph(callgraph_path).expect("No graph?");
-    let mut node_index: usize = 0;
+fn analyze_graph_for_package(graph: &CompactGraph, crate_name: &str) -> Labels{
+    let mut labels: Labels = vec![None; graph.len()];
+    let package = graph.symbol(crate_name);
+    if package.is_none(){
+        return labels;
+    }
+
+    for node_index in graph.nodes(){
+        if graph.package_symbol(node_index) == package{
+            traverse_node_downwards(graph, &mut labels, node_index, package, false);
+        }
+    }
+
+    labels
+}
+
+fn analyze_graph_for_package2(graph: &CompactGraph, crate_name: &str, main_package: &str, deps: &[(String, String)]) -> Labels{
+    let mut labels: Labels = vec![None; graph.len()];
+    let package = graph.symbol(crate_name);
+    let main_package = graph.symbol(main_package);
     let mut private_list: Vec<usize> = Vec::new();
 
-    while dep_graph.len() > node_index{
-        let node = dep_graph.get(node_index).unwrap();
-        let mut called = false;
-        if &node.package_name == &Some(crate_name.to_string()){
-            for e in &node.inward_edges{
-                if &Some(main_package.to_string()) == &dep_graph.get(e.target).unwrap().package_name{
-                    called = true;
-                    break;
-                }
-            }
+    for node_index in graph.nodes(){
+        if package.is_some() && graph.package_symbol(node_index) == package{
+            let called = main_package.is_some()
+                && graph.callers(node_index).iter().any(|caller| graph.package_symbol(*caller as usize) == main_package);
             if called {
-                traverse_node_downwards(&mut dep_graph, node_index.clone(), crate_name, true);
+                traverse_node_downwards(graph, &mut labels, node_index, package, true);
             }else{
                 private_list.push(node_index);
             }
         }
-
-        node_index += 1;
     }
 
-    for mut d in &mut dep_graph{
-        if d.node_type == None{
-            for dep in deps{
-                if &d.package_name == &Some((&dep.0).to_string()) && d.package_version == Some(dep.1.to_string()){
-                    d.node_type = Some("local_func".to_string());
+    let dep_packages: HashSet<(Symbol, Symbol)> = deps.iter()
+        .filter_map(|dep| Some((graph.symbol(&dep.0)?, graph.symbol(&dep.1)?)))
+        .collect();
+    for d in graph.nodes(){
+        if labels[d].is_none(){
+            if let (Some(package), Some(version)) = (graph.package_symbol(d), graph.version_symbol(d)){
+                if dep_packages.contains(&(package, version)){
+                    labels[d] = Some(NodeClass::LocalFunc);
                 }
             }
         }
     }
 
     for i in private_list{
-        traverse_node_downwards(&mut dep_graph, i.clone(), crate_name, false);
+        traverse_node_downwards(graph, &mut labels, i, package, false);
+    }
+
+    labels
+}
+
+/// Breadth-first search from all functions of `crate_name` at once. Returns
+/// the number of calls to each reached node and the node it was reached from.
+/// It follows the same edges as the crate-level classification, so a
+/// dependency function has a depth exactly when it counts as used.
+fn search_from_crate(graph: &CompactGraph, crate_name: &str) -> (Vec<Option<usize>>, Vec<Option<usize>>){
+    let mut depths: Vec<Option<usize>> = vec![None; graph.len()];
+    let mut parents: Vec<Option<usize>> = vec![None; graph.len()];
+    let mut queue = VecDeque::new();
+    let package = graph.symbol(crate_name);
+    for n in graph.nodes(){
+        if package.is_some() && graph.package_symbol(n) == package{
+            depths[n] = Some(0);
+            queue.push_back(n);
+        }
+    }
+
+    while let Some(current) = queue.pop_front(){
+        let depth = depths[current].unwrap() + 1;
+        for target in graph.callees(current){
+            let target = *target as usize;
+            if depths[target].is_none(){
+                depths[target] = Some(depth);
+                parents[target] = Some(current);
+                queue.push_back(target);
+            }
+        }
     }
 
-    dep_graph
+    (depths, parents)
+}
+
+/// A shortest chain of calls from any function of `crate_name` to `target`,
+/// as node ids starting at the local function.
+pub fn shortest_call_path(graph: &CompactGraph, crate_name: &str, target: usize) -> Option<Vec<usize>>{
+    let (depths, parents) = search_from_crate(graph, crate_name);
+    depths[target]?;
+
+    let mut path = vec![target];
+    while let Some(parent) = parents[*path.last().unwrap()]{
+        path.push(parent);
+    }
+    path.reverse();
+    Some(path)
 }
 
-fn traverse_node_downwards(graph: &mut Vec<Node>, node_index: usize, package_name: &String, public: bool){
+/// Labels everything reachable from `node_index`. Functions of `package` are
+/// local, other packages' are dependency functions.
+fn traverse_node_downwards(graph: &CompactGraph, labels: &mut Labels, node_index: usize, package: Option<Symbol>, public: bool){
     let mut current_level_index = 0;
 
     let mut current_level_indexes: Vec<usize> = Vec::new();
-    current_level_indexes.push(node_index.clone());
+    current_level_indexes.push(node_index);
     let mut next_level_indexes: Vec<usize> = Vec::new();
 
 
     while current_level_indexes.len() > current_level_index{ 
-        let current_index = current_level_indexes.get(current_level_index).cloned().unwrap();       
-        let current_node = graph.get_mut(current_index).unwrap();
+        let current_index = current_level_indexes[current_level_index];
+        let current_package = graph.package_symbol(current_index);
+        let label = &mut labels[current_index];
         if public{
-            if current_node.node_type == None{
-                for edge in &current_node.outward_edges{
-                    next_level_indexes.push(edge.target.clone());
-                }
+            if label.is_none(){
+                next_level_indexes.extend(graph.callees(current_index).iter().map(|target| *target as usize));
             }
 
-            if &current_node.package_name == &Some(package_name.to_string()){
-                if &current_node.relative_def_id == &"pango_sys[3ddd]::[0]::pango_coverage_copy[0]".to_string(){
-                }
-                current_node.node_type = Some("local_func_pub".to_string());
-            }else if current_node.package_name == None{
-                current_node.node_type = Some("std_func".to_string());
+            if current_package.is_some() && current_package == package{
+                *label = Some(NodeClass::LocalFuncPub);
+            }else if current_package.is_none(){
+                *label = Some(NodeClass::StdFunc);
             }else {
-                current_node.node_type = Some("used_dep_func_pub".to_string());
+                *label = Some(NodeClass::UsedDepFuncPub);
             }
         }else{
-            match current_node.node_type {
-                None => {
-                    if &current_node.package_name == &Some(package_name.to_string()){
-                        current_node.node_type = Some("local_func".to_string());
-                    }else if current_node.package_name == None{
-                        current_node.node_type = Some("std_func".to_string());
-                    }else {
-                        current_node.node_type = Some("used_dep_func".to_string());
-                    }
-    
-                    for edge in &current_node.outward_edges{
-                        next_level_indexes.push(edge.target.clone());
-                    }
-                },
-                Some(_) => ()
+            if label.is_none(){
+                if current_package.is_some() && current_package == package{
+                    *label = Some(NodeClass::LocalFunc);
+                }else if current_package.is_none(){
+                    *label = Some(NodeClass::StdFunc);
+                }else {
+                    *label = Some(NodeClass::UsedDepFunc);
+                }
+
+                next_level_indexes.extend(graph.callees(current_index).iter().map(|target| *target as usize));
             }
         }        
 
@@ -301,56 +598,101 @@ fn traverse_node_downwards(graph: &mut Vec<Node>, node_index: usize, package_nam
 }
 
 
-fn update_with_python(path: &PathBuf, update_path: &PathBuf) -> Result<PathBuf>{
-    let res_path = update_path.join("updated_callgraph.json");
-    if res_path.exists() {
-        return Ok(res_path)
+/// Graphs regrouped by the old grapher.py are still picked up from the update
+/// directory, everything else is streamed straight from `callgraph.json`.
+/// With `graph_cache` the parsed graph is also kept in the update directory.
+/// Graphs with more functions and calls than `max_graph_size` are rejected.
+/// Next to the graph comes why it could not be cached, if writing the cache
+/// failed, for the caller to report.
+pub fn load_crate_graph_dir(path: &PathBuf, update_path: &PathBuf, graph_cache: bool, max_graph_size: Option<usize>) -> Result<(CompactGraph, Option<io::Error>)>{
+    if graph_cache {
+        return load_cached_call_graph(path, update_path, max_graph_size);
     }
 
-    let output = Command::new("python3")
-        .arg("grapher.py")
-        .arg(path)
-        .arg(update_path)
-        .output()?;
-    
-    if output.status.success() {
-        return Ok(res_path)
-    }else{
-        println!("{}", str::from_utf8(&output.stdout).unwrap());
-        println!("{}", str::from_utf8(&output.stderr).unwrap());
-        return Err("Bad request")?;
+    let legacy_path = update_path.join("updated_callgraph.json");
+    if legacy_path.exists() {
+        return Ok((read_legacy_callgraph(&legacy_path, max_graph_size)?, None));
     }
+
+    Ok((read_callgraph_json(&path.join("callgraph.json"), max_graph_size)?, None))
 }
 
-fn get_call_graph(path: &PathBuf) -> Result<Vec<Node>>{
-    if path.exists() {
-        let file = File::open(path).expect("could not open graph file");
-        let buffered_reader = BufReader::new(file);
-        let res = from_reader(buffered_reader)?;
-        return Ok(res);
-    }else{
-        return Err("Bad request")?;
+/// Loads the binary cache of a callgraph if it was built from the current
+/// source file, otherwise parses the source and rebuilds the cache. A cache
+/// that can't be written only costs the speedup, the failure is returned
+/// with the graph.
+fn load_cached_call_graph(path: &Path, update_path: &Path, max_graph_size: Option<usize>) -> Result<(CompactGraph, Option<io::Error>)>{
+    let legacy_path = update_path.join("updated_callgraph.json");
+    let is_legacy = legacy_path.exists();
+    let source_path = if is_legacy { legacy_path } else { path.join("callgraph.json") };
+    if !source_path.exists() {
+        return Err(AnalysisError::CallgraphMissing(source_path));
     }
+
+    let hash = graph_cache::source_hash(File::open(&source_path)?)?;
+    let cache_path = update_path.join(graph_cache::CACHE_FILE);
+    match graph_cache::read_cache(&cache_path, hash, max_graph_size) {
+        Ok(Some(graph)) => return Ok((graph, None)),
+        Err(NormalizeError::TooLarge(limit)) => return Err(AnalysisError::GraphTooLarge(limit)),
+        // Missing, stale or broken caches are rebuilt.
+        _ => ()
+    }
+
+    let graph = if is_legacy {
+        read_legacy_callgraph(&source_path, max_graph_size)?
+    } else {
+        read_callgraph_json(&source_path, max_graph_size)?
+    };
+    let cached = create_dir_all(update_path).and_then(|_| graph_cache::write_cache(&cache_path, &graph, hash));
+    Ok((graph, cached.err()))
 }
 
-fn get_deps(crate_path: &PathBuf, crate_name: &String, version: Version) -> Result<Vec<(String, String)>>{
-    let lockfile = Lockfile::load(crate_path.join("Cargo.lock"))?;
-    let mut result: Vec<(String, String)> = Vec::new();
+/// Streams a callgraph in the format Präzi writes into a `CompactGraph`,
+/// giving up once it has more functions and calls than `max_graph_size`.
+pub fn read_callgraph_json(callgraph_path: &Path, max_graph_size: Option<usize>) -> Result<CompactGraph>{
+    if !callgraph_path.exists() {
+        return Err(AnalysisError::CallgraphMissing(callgraph_path.to_path_buf()));
+    }
+    let file = File::open(callgraph_path)?;
+    Ok(stream_callgraph(BufReader::new(file), max_graph_size)?)
+}
+
+/// Streams a graph regrouped by the old grapher.py, see `read_callgraph_json`.
+fn read_legacy_callgraph(path: &Path, max_graph_size: Option<usize>) -> Result<CompactGraph>{
+    let file = File::open(path)?;
+    Ok(stream_legacy_callgraph(BufReader::new(file), max_graph_size)?)
+}
+
+/// Reads a `Cargo.lock` file.
+pub fn load_lockfile(lockfile_path: &Path) -> Result<Lockfile>{
+    if !lockfile_path.exists() {
+        return Err(AnalysisError::MissingLockfile(lockfile_path.to_path_buf()));
+    }
+    Lockfile::load(lockfile_path).map_err(AnalysisError::MalformedLockfile)
+}
+
+fn find_package<'a>(lockfile: &'a Lockfile, crate_name: &String, version: &Version) -> Result<&'a cargo_lock::package::Package>{
+    lockfile.packages.iter()
+        .find(|d| &d.name.to_string() == crate_name && &d.version == version)
+        .ok_or_else(|| AnalysisError::PackageNotInLockfile(crate_name.to_string(), version.to_string()))
+}
+
+fn get_deps(lockfile: &Lockfile, crate_name: &String, version: &Version) -> Result<Vec<(String, Version)>>{
+    let mut result: Vec<(String, Version)> = Vec::new();
 
-    let deps = lockfile.packages.iter().find(|d| &d.name.to_string() == crate_name && &d.version == &version).unwrap();
+    let deps = find_package(lockfile, crate_name, version)?;
 
     for d in &deps.dependencies{
-        result.push((d.name.to_string(), d.version.to_string()));
+        result.push((d.name.to_string(), d.version.clone()));
     }        
 
     Ok(result)   
 }
 
-fn get_all_deps(crate_path: &PathBuf, crate_name: &String, version: Version) -> Result<Vec<(String, String)>>{
-    let lockfile = Lockfile::load(crate_path.join("Cargo.lock"))?;
+fn get_all_deps(lockfile: &Lockfile, crate_name: &String, version: &Version) -> Result<Vec<(String, String)>>{
     let mut result: Vec<(String, String)> = Vec::new();
 
-    let deps = lockfile.packages.iter().find(|d| &d.name.to_string() == crate_name && &d.version == &version).unwrap();
+    let deps = find_package(lockfile, crate_name, version)?;
 
     let mut current_level_index = 0;
     let mut current_level_deps: Vec<&cargo_lock::dependency::Dependency> = Vec::new();
@@ -365,7 +707,7 @@ fn get_all_deps(crate_path: &PathBuf, crate_name: &String, version: Version) ->
 
         
         // println!("Dep - {} - {}", current_dep.name, current_dep.version);
-        let dependency = &lockfile.packages.iter().find(|d| d.name == current_dep.name && d.version == current_dep.version).unwrap();
+        let dependency = find_package(lockfile, &current_dep.name.to_string(), &current_dep.version)?;
 
         // let ten_millis = time::Duration::from_millis(100);
         // let now = time::Instant::now();
@@ -409,4 +751,132 @@ fn get_all_deps(crate_path: &PathBuf, crate_name: &String, version: Version) ->
 //     }
 
 //     result
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact_graph::CompactGraphBuilder;
+
+    /// Functions as `(package, def_id)`, `None` for std, and calls between
+    /// their positions.
+    fn graph(functions: &[(Option<&str>, &str)], calls: &[(usize, usize)]) -> CompactGraph {
+        let mut builder = CompactGraphBuilder::default();
+        for (package, def_id) in functions {
+            let crate_name = def_id.split(&[':', '['][..]).next().unwrap();
+            builder.add_function(*package, package.map(|_| "1.0.0"), crate_name, def_id, 10, true).unwrap();
+        }
+        for (source, target) in calls {
+            builder.add_call(*source, *target, false).unwrap();
+        }
+        builder.build()
+    }
+
+    fn call_chain() -> CompactGraph {
+        graph(&[
+            (Some("app"), "app::main"),
+            (Some("app"), "app::helper"),
+            (Some("dep"), "dep::a"),
+            (Some("dep"), "dep::b"),
+            (Some("dep"), "dep::unreached"),
+            (None, "core::fmt::write")
+        ], &[(0, 1), (1, 2), (2, 3), (0, 3), (3, 5), (4, 0)])
+    }
+
+    #[test]
+    fn depths_count_calls_from_the_nearest_local_function() {
+        let (depths, parents) = search_from_crate(&call_chain(), "app");
+        assert_eq!(depths, vec![Some(0), Some(0), Some(1), Some(1), None, Some(2)]);
+        assert_eq!(parents, vec![None, None, Some(1), Some(0), None, Some(3)]);
+    }
+
+    #[test]
+    fn depths_are_empty_for_unknown_crates() {
+        let (depths, _) = search_from_crate(&call_chain(), "other");
+        assert!(depths.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn call_paths_start_at_a_local_function() {
+        let graph = call_chain();
+        assert_eq!(shortest_call_path(&graph, "app", 5), Some(vec![0, 3, 5]));
+        assert_eq!(shortest_call_path(&graph, "app", 2), Some(vec![1, 2]));
+        assert_eq!(shortest_call_path(&graph, "app", 0), Some(vec![0]));
+        assert_eq!(shortest_call_path(&graph, "app", 4), None);
+    }
+
+    #[test]
+    fn rolls_functions_up_into_modules_and_impl_blocks() {
+        let graph = graph(&[
+            (Some("dep"), "dep[aaaa]::de[0]::from_str[0]"),
+            (Some("dep"), "dep[aaaa]::de[0]::{{impl}}[3]::deserialize[0]"),
+            (Some("dep"), "dep[aaaa]::de[0]::{{impl}}[3]::{{closure}}[0]"),
+            (Some("dep"), "dep[aaaa]::ser[0]::to_string[0]"),
+            (Some("dep"), "[0]::no_crate[0]"),
+            (Some("app"), "app[bbbb]::main[0]")
+        ], &[]);
+        let counted = |n: &usize| *n != 5;
+        let used = |n: &usize| *n == 0 || *n == 2;
+
+        let paths: Vec<_> = roll_up_paths(&graph, "dep", "1.0.0", counted, used).into_iter()
+            .map(|p| (p.kind.as_str(), p.path, p.used_count, p.total_count, p.used_loc, p.total_loc))
+            .collect();
+        assert_eq!(paths, vec![
+            ("module", "dep::de".to_string(), 2, 3, 20, 30),
+            ("module", "dep::ser".to_string(), 0, 1, 0, 10),
+            ("impl", "dep::de::{impl#3}".to_string(), 1, 2, 10, 20)
+        ]);
+    }
+
+    #[test]
+    fn splits_bloat_of_shared_transitive_dependencies() {
+        let graph = graph(&[
+            (Some("app"), "app::main"),
+            (Some("x"), "x::a"),
+            (Some("x"), "x::unused"),
+            (Some("y"), "y::unused"),
+            (Some("shared"), "shared::used"),
+            (Some("shared"), "shared::unused"),
+            (Some("only_x"), "only_x::unused")
+        ], &[(0, 1), (1, 4)]);
+        let lockfile: Lockfile = r#"
+            [[package]]
+            name = "app"
+            version = "1.0.0"
+            dependencies = ["x 1.0.0", "y 1.0.0"]
+
+            [[package]]
+            name = "x"
+            version = "1.0.0"
+            dependencies = ["only_x 1.0.0", "shared 1.0.0"]
+
+            [[package]]
+            name = "y"
+            version = "1.0.0"
+            dependencies = ["shared 1.0.0", "no_functions 1.0.0"]
+
+            [[package]]
+            name = "shared"
+            version = "1.0.0"
+
+            [[package]]
+            name = "only_x"
+            version = "1.0.0"
+
+            [[package]]
+            name = "no_functions"
+            version = "1.0.0"
+        "#.parse().unwrap();
+
+        let metrics = compute_metrics(&graph, &lockfile, "app", &Version::parse("1.0.0").unwrap()).unwrap();
+        let bloat: Vec<_> = metrics.bloat.iter()
+            .map(|b| (b.name.as_str(), b.package_count, b.unused_count, b.unused_loc, b.exclusive_unused_count, b.exclusive_unused_loc))
+            .collect();
+        // The unused function of `shared` counts for both dependencies but is
+        // exclusive to neither.
+        assert_eq!(bloat, vec![
+            ("x", 3, 3, 30, 2, 20),
+            ("y", 3, 2, 20, 1, 10)
+        ]);
+    }
+}
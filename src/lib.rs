@@ -0,0 +1,32 @@
+//! Measures how much of their dependencies Rust crates actually use, based on
+//! the callgraphs Präzi builds for them.
+//!
+//! The metrics of a single crate can be computed without the dataset layout or
+//! a database:
+//!
+//! ```no_run
+//! use crate_analyzer::{compute_metrics, load_lockfile, read_callgraph_json};
+//! use semver::Version;
+//! use std::path::Path;
+//!
+//! let graph = read_callgraph_json(Path::new("callgraph.json"), None).unwrap();
+//! let lockfile = load_lockfile(Path::new("Cargo.lock")).unwrap();
+//! let metrics = compute_metrics(&graph, &lockfile, "rand", &Version::parse("0.7.3").unwrap()).unwrap();
+//! println!("{} of {} dependency functions used", metrics.UsedDepFuncCount, metrics.TotalDepFuncCount);
+//! ```
+pub mod callgraph;
+pub mod compact_graph;
+pub mod dataset;
+pub mod def_path;
+pub mod errors;
+pub mod export;
+pub mod features;
+pub mod graph_cache;
+pub mod index_calculator;
+pub mod report;
+pub mod sqlite_handler;
+#[macro_use] extern crate quick_error;
+
+pub use compact_graph::CompactGraph;
+pub use errors::AnalysisError;
+pub use index_calculator::{compute_metrics, load_lockfile, read_callgraph_json, DepBloat, DepFunc, DepMetric, Edge, Metrics, Node, PathKind, PathMetric};
@@ -1,77 +1,479 @@
-pub mod index_calculator;
-pub mod sqlite_handler;
-#[macro_use] extern crate quick_error;
-use index_calculator::get_index;
-use sqlite_handler::SqliteHandler;
-use std::fs;
-use semver::{Version};
-use std::path::PathBuf;
-use std::env;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use crate_analyzer::compact_graph::{CompactGraph, Symbol};
+use crate_analyzer::dataset::{analyze_dataset, export_dataset, DatasetOptions, VersionSelection};
+use crate_analyzer::export::{ExportFormat, Exporter};
+use crate_analyzer::features::{self, Manifest};
+use crate_analyzer::index_calculator::{get_index, load_crate_graph_dir, shortest_call_path, Metrics};
+use crate_analyzer::report;
+use crate_analyzer::sqlite_handler::SqliteHandler;
+use semver::VersionReq;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const DEFAULT_DATA_PATH: &str = "/data/praezi/batch/data/";
+const DEFAULT_CACHE_PATH: &str = "/data/praezi_algirdas/datasets/";
+const DEFAULT_DATABASE_PATH: &str = "/database/prazi.db";
+
+fn data_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("data-dir")
+            .long("data-dir")
+            .value_name("DIR")
+            .default_value(DEFAULT_DATA_PATH)
+            .help("Root of the Präzi callgraphs, laid out as <crate>/<version>/callgraph.json"),
+        Arg::with_name("cache-dir")
+            .long("cache-dir")
+            .value_name("DIR")
+            .default_value(DEFAULT_CACHE_PATH)
+            .help("Root of the per-crate graph cache, laid out like --data-dir"),
+        Arg::with_name("graph-cache")
+            .long("graph-cache")
+            .help("Keep parsed callgraphs as binary files in --cache-dir and load them from there"),
+        Arg::with_name("max-graph-size")
+            .long("max-graph-size")
+            .value_name("N")
+            .help("Skip crate versions whose callgraph has more than N functions and calls together"),
+    ]
+}
+
+fn selection_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("crate")
+            .long("crate")
+            .value_name("NAME")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only analyze the given crate, can be repeated"),
+        Arg::with_name("limit")
+            .long("limit")
+            .value_name("N")
+            .help("Stop after N crates"),
+        Arg::with_name("jobs")
+            .long("jobs")
+            .short("j")
+            .value_name("N")
+            .default_value("1")
+            .help("Number of crates analyzed in parallel"),
+        Arg::with_name("all-versions")
+            .long("all-versions")
+            .conflicts_with_all(&["latest", "version-req"])
+            .help("Analyze every version of a crate instead of only the highest one"),
+        Arg::with_name("latest")
+            .long("latest")
+            .value_name("N")
+            .conflicts_with("version-req")
+            .help("Analyze the N highest versions of every crate"),
+        Arg::with_name("version-req")
+            .long("version-req")
+            .value_name("REQ")
+            .help("Analyze the versions matching a semver requirement, e.g. \">=1.0, <2.0\""),
+    ]
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let database_path = match args.get(1) {
-        None => {
-            "/database/prazi.db"
+    let matches = App::new("crate_analyzer")
+        .about("Measures how much of their dependencies crates actually use")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("database")
+            .long("database")
+            .value_name("FILE")
+            .default_value(DEFAULT_DATABASE_PATH)
+            .global(true)
+            .help("SQLite database the metrics are stored in"))
+        .subcommand(SubCommand::with_name("analyze")
+            .about("Analyzes every crate of a dataset and stores the metrics, or prints the metrics of a single crate")
+            .args(&data_args())
+            .arg(Arg::with_name("name")
+                .requires("version")
+                .help("Only analyze this crate and print its metrics"))
+            .arg(Arg::with_name("version")
+                .help("Version of the single crate to analyze"))
+            .arg(Arg::with_name("save")
+                .long("save")
+                .requires("name")
+                .help("Also store the metrics of the single crate in the database"))
+            .arg(Arg::with_name("suggest")
+                .long("suggest")
+                .requires("name")
+                .help("Suggest unused dependencies and features to turn off, read from the Cargo.toml of the crate and of its dependencies in the data directory"))
+            .args(&selection_args())
+            .arg(Arg::with_name("resume")
+                .long("resume")
+                .conflicts_with("force")
+                .help("Skip crate versions that already have metrics or errors in the database"))
+            .arg(Arg::with_name("force")
+                .long("force")
+                .help("Recompute crate versions and replace the rows stored for them"))
+            .arg(Arg::with_name("ignore-state")
+                .long("ignore-state")
+                .help("Also analyze crates Präzi has not marked as having a full callgraph")))
+        .subcommand(SubCommand::with_name("export")
+            .about("Analyzes every crate of a dataset and writes the metrics to JSON Lines or CSV files")
+            .args(&data_args())
+            .args(&selection_args())
+            .arg(Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .value_name("DIR")
+                .required(true)
+                .help("Directory the metrics, dep_metrics, dep_path_metrics, bloat and functions files are written to"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["jsonl", "csv"])
+                .default_value("jsonl")
+                .help("Format of the exported files")))
+        .subcommand(SubCommand::with_name("report")
+            .about("Summarizes what is stored in the database")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("errors")
+                .about("Counts failed crate versions per error kind and stage")
+                .arg(Arg::with_name("kind")
+                    .long("kind")
+                    .value_name("KIND")
+                    .help("List the failures of one kind instead"))
+                .arg(Arg::with_name("limit")
+                    .long("limit")
+                    .value_name("N")
+                    .default_value("20")
+                    .help("Number of failures listed with --kind")))
+            .subcommand(SubCommand::with_name("indices")
+                .about("Computes leanness, dependency and utilization indices over all analyzed crates")
+                .arg(Arg::with_name("min-dependents")
+                    .long("min-dependents")
+                    .value_name("N")
+                    .default_value("5")
+                    .help("Only include dependencies with more than N dependents in the utilization index"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .possible_values(&["text", "json"])
+                    .default_value("text")))
+            .subcommand(SubCommand::with_name("dep")
+                .about("Ranks the functions of a dependency by how many analyzed crates use them")
+                .arg(Arg::with_name("name").required(true))
+                .arg(Arg::with_name("version")
+                    .help("Only this version of the dependency instead of all of them"))
+                .arg(Arg::with_name("include-transitive")
+                    .long("include-transitive")
+                    .help("Also list functions of the dependency's own dependencies"))
+                .arg(Arg::with_name("limit")
+                    .long("limit")
+                    .value_name("N")
+                    .default_value("50")
+                    .help("Number of functions listed"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .possible_values(&["text", "json"])
+                    .default_value("text")))
+            .subcommand(SubCommand::with_name("dead-api")
+                .about("Lists public functions of a crate that no analyzed dependent reaches")
+                .arg(Arg::with_name("name").required(true))
+                .arg(Arg::with_name("version")
+                    .help("Only this version of the crate instead of all of them"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .possible_values(&["text", "json"])
+                    .default_value("text"))))
+        .subcommand(SubCommand::with_name("inspect")
+            .about("Prints an overview of a single crate's callgraph")
+            .args(&data_args())
+            .arg(Arg::with_name("name").required(true))
+            .arg(Arg::with_name("version").required(true)))
+        .subcommand(SubCommand::with_name("explain")
+            .about("Prints a shortest call path from the crate's own code to a dependency function")
+            .args(&data_args())
+            .arg(Arg::with_name("name").required(true))
+            .arg(Arg::with_name("version").required(true))
+            .arg(Arg::with_name("function")
+                .required(true)
+                .help("relative_def_id of the function, or a unique part of it")))
+        .get_matches();
+
+    match matches.subcommand() {
+        ("analyze", Some(sub)) => run_analyze(&matches, sub),
+        ("export", Some(sub)) => run_export(sub),
+        ("report", Some(sub)) => run_report(&matches, sub),
+        ("inspect", Some(sub)) => run_inspect(sub),
+        ("explain", Some(sub)) => run_explain(sub),
+        _ => unreachable!()
+    }
+}
+
+fn parse_number(matches: &ArgMatches, name: &str) -> Option<usize> {
+    matches.value_of(name).map(|v| match v.parse() {
+        Ok(n) => n,
+        Err(_) => {
+            eprintln!("--{} expects a number, got {}", name, v);
+            process::exit(exitcode::USAGE);
+        }
+    })
+}
+
+fn parse_version_selection(matches: &ArgMatches) -> VersionSelection {
+    if matches.is_present("all-versions") {
+        return VersionSelection::All;
+    }
+    if let Some(count) = parse_number(matches, "latest") {
+        return VersionSelection::Latest(count);
+    }
+    match matches.value_of("version-req") {
+        None => VersionSelection::Highest,
+        Some(req) => match VersionReq::parse(req) {
+            Ok(req) => VersionSelection::Matching(req),
+            Err(why) => {
+                eprintln!("Invalid --version-req {}: {}", req, why);
+                process::exit(exitcode::USAGE);
+            }
+        }
+    }
+}
+
+fn run_analyze(matches: &ArgMatches, sub: &ArgMatches) {
+    if let Some(name) = sub.value_of("name") {
+        return run_analyze_single(matches, sub, name, sub.value_of("version").unwrap());
+    }
+
+    let db_handler = SqliteHandler::new(matches.value_of("database").unwrap());
+    let options = dataset_options(sub);
+    if let Err(why) = analyze_dataset(&options, &db_handler) {
+        exit_unreadable_data_dir(&options, why);
+    }
+}
+
+fn exit_unreadable_data_dir(options: &DatasetOptions, why: io::Error) -> ! {
+    eprintln!("Could not read the data directory {}: {}", options.data_path.display(), why);
+    process::exit(exitcode::NOINPUT);
+}
+
+fn dataset_options(sub: &ArgMatches) -> DatasetOptions {
+    DatasetOptions {
+        data_path: PathBuf::from(sub.value_of("data-dir").unwrap()),
+        cache_path: PathBuf::from(sub.value_of("cache-dir").unwrap()),
+        crate_filter: sub.values_of("crate").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+        limit: parse_number(sub, "limit"),
+        versions: parse_version_selection(sub),
+        jobs: parse_number(sub, "jobs").unwrap(),
+        resume: sub.is_present("resume"),
+        force: sub.is_present("force"),
+        ignore_state: sub.is_present("ignore-state"),
+        graph_cache: sub.is_present("graph-cache"),
+        max_graph_size: parse_number(sub, "max-graph-size")
+    }
+}
+
+fn run_export(sub: &ArgMatches) {
+    let format = ExportFormat::parse(sub.value_of("format").unwrap()).unwrap();
+    let mut exporter = match Exporter::create(Path::new(sub.value_of("output").unwrap()), format) {
+        Err(why) => {
+            eprintln!("{}", why);
+            process::exit(exitcode::CANTCREAT);
+        },
+        Ok(exporter) => exporter
+    };
+    let options = dataset_options(sub);
+    if let Err(why) = export_dataset(&options, &mut exporter) {
+        exit_unreadable_data_dir(&options, why);
+    }
+}
+
+fn run_analyze_single(matches: &ArgMatches, sub: &ArgMatches, name: &str, version: &str) {
+    let crate_name = name.to_string();
+    let crate_version = version.to_string();
+    let crate_path = PathBuf::from(sub.value_of("data-dir").unwrap()).join(name).join(version);
+    let cache_path = PathBuf::from(sub.value_of("cache-dir").unwrap()).join(name).join(version);
+
+    let metrics = match get_index(&crate_path, &cache_path, &crate_name, &crate_version, sub.is_present("graph-cache"), parse_number(sub, "max-graph-size")) {
+        Err(why) => {
+            eprintln!("Analyzing {} - {} failed: {}", name, version, why);
+            process::exit(exitcode::DATAERR);
         },
-        Some(v) => {
-            v
+        Ok((metrics, cache_error)) => {
+            warn_uncached(&cache_path, cache_error);
+            metrics
         }
     };
-    let db_handler = SqliteHandler::new(database_path);
-
-    let mut counter = 0;
-    // let data_path = "/data/praezi/batch/data/";
-    // let updated_data_path = "/data/praezi_algirdas/datasets/";
-    let data_path = "/home/algirdas/crate_data/";
-    let updated_data_path = "/home/algirdas/crate_data2/";
-    println!("Processing data in {}", data_path);
-    let total_paths = fs::read_dir(data_path).unwrap().count();
-    let paths = fs::read_dir(data_path).unwrap();
-    for path in paths {
-        let pather = path.unwrap();
-        match fs::read_dir(pather.path()){
-            Err(why) => {
-                println!("Failed reading versions in {:?} - {:?}", pather, why);
-            },
-            Ok(versions) => {
-                let mut highest_version = Version::parse(&"0.0.0").unwrap();
-                let mut highest_update_path = PathBuf::from(updated_data_path);
-                let mut highest_path = PathBuf::new();
-                let mut highest_ver_str = String::new();
-                for version_folder in versions{
-                    let crate_path = version_folder.unwrap().path();
-                    let crate_version = crate_path.file_name().unwrap().to_string_lossy().to_string();
-                    let v = Version::parse(&crate_version).unwrap();
-                    if v > highest_version{
-                        highest_version = v;
-                        highest_path = crate_path;
-                        highest_ver_str = crate_version;
-                    }
-                }
-
-                if !highest_ver_str.is_empty() {
-                    let crate_name = pather.path().file_name().unwrap().to_string_lossy().to_string();
-                    highest_update_path = highest_update_path.join(updated_data_path).join(&crate_name).join(&highest_ver_str);
-
-                    println!("Processing {} - {}", &crate_name, &highest_version);
-                    
-                    match get_index(&highest_path, &highest_update_path, &crate_name, &highest_ver_str){
-                        // Err(ref e) if e. == std::io::ErrorKind::NotFound => {
-                            
-                        // },
-                        Err(why) => {
-                            db_handler.insert_error(format!("{:?}", why), &crate_name, &highest_ver_str);
-                            println!("Failed for {:?}", why);
-                        },
-                        Ok(val) => {
-                            db_handler.insert_metric(&val, &crate_name, &highest_ver_str);
-                            println!("Success ({}/{}) for - {} - {}", counter, total_paths, &crate_name, &highest_version);
-                        }
-                    }
-    
-                }
-                counter+=1;
+    report::print_metrics(&metrics, name, version);
+
+    if sub.is_present("suggest") {
+        println!();
+        match Manifest::load(&crate_path.join("Cargo.toml")) {
+            Err(why) => println!("No suggestions: {}", why),
+            Ok(manifest) => {
+                let dep_manifests = load_dep_manifests(&metrics, Path::new(sub.value_of("data-dir").unwrap()));
+                report::print_suggestions(&features::suggest(&metrics, &manifest, &dep_manifests))
+            }
+        }
+    }
+
+    if sub.is_present("save") {
+        let db_handler = SqliteHandler::new(matches.value_of("database").unwrap());
+        let stored = if sub.is_present("force") {
+            db_handler.replace_metric(&metrics, &crate_name, &crate_version)
+        } else {
+            db_handler.insert_metric(&metrics, &crate_name, &crate_version)
+        };
+        if let Err(why) = stored {
+            eprintln!("Could not store the metrics: {}", why);
+            process::exit(exitcode::IOERR);
+        }
+    }
+}
+
+/// Manifests of the used dependencies that are part of the data directory too.
+fn load_dep_manifests(metrics: &Metrics, data_path: &Path) -> HashMap<(String, String), Manifest> {
+    metrics.depMetrics.iter()
+        .filter(|dep| dep.usedCount > 0)
+        .filter_map(|dep| {
+            let path = data_path.join(&dep.crate_name).join(&dep.crate_version).join("Cargo.toml");
+            let manifest = Manifest::load(&path).ok()?;
+            Some(((dep.crate_name.to_string(), dep.crate_version.to_string()), manifest))
+        })
+        .collect()
+}
+
+fn run_report(matches: &ArgMatches, sub: &ArgMatches) {
+    let db_handler = SqliteHandler::new(matches.value_of("database").unwrap());
+    match sub.subcommand() {
+        ("errors", Some(errors)) => match errors.value_of("kind") {
+            None => report::print_error_summary(&db_handler),
+            Some(kind) => report::print_errors_of_kind(&db_handler, kind, parse_number(errors, "limit").unwrap() as u32)
+        },
+        ("indices", Some(indices)) => {
+            let min_dependents = parse_number(indices, "min-dependents").unwrap() as u32;
+            let result = report::indices::compute_indices(&db_handler, min_dependents);
+            print_report(indices, &result, report::indices::print_indices);
+        },
+        ("dep", Some(dep)) => {
+            let result = report::dependency::compute_dependency_usage(
+                &db_handler,
+                dep.value_of("name").unwrap(),
+                dep.value_of("version"),
+                dep.is_present("include-transitive"),
+                parse_number(dep, "limit").unwrap());
+            print_report(dep, &result, report::dependency::print_dependency_usage);
+        },
+        ("dead-api", Some(dead_api)) => {
+            let result = report::dead_api::compute_dead_api(
+                &db_handler,
+                dead_api.value_of("name").unwrap(),
+                dead_api.value_of("version"));
+            print_report(dead_api, &result, report::dead_api::print_dead_api);
+        },
+        _ => unreachable!()
+    }
+}
+
+/// Prints a report as text or, with `--format json`, as pretty JSON.
+fn print_report<T: Serialize>(matches: &ArgMatches, result: &T, print_text: fn(&T)) {
+    if matches.value_of("format") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(result).unwrap());
+    } else {
+        print_text(result);
+    }
+}
+
+/// Loads the callgraph of the crate named by the `name` and `version`
+/// arguments, or exits.
+fn load_crate_graph(sub: &ArgMatches) -> CompactGraph {
+    let name = sub.value_of("name").unwrap();
+    let version = sub.value_of("version").unwrap();
+    let crate_path = PathBuf::from(sub.value_of("data-dir").unwrap()).join(name).join(version);
+    let cache_path = PathBuf::from(sub.value_of("cache-dir").unwrap()).join(name).join(version);
+
+    match load_crate_graph_dir(&crate_path, &cache_path, sub.is_present("graph-cache"), parse_number(sub, "max-graph-size")) {
+        Err(why) => {
+            eprintln!("Could not load the callgraph of {} - {}: {}", name, version, why);
+            process::exit(exitcode::NOINPUT);
+        },
+        Ok((graph, cache_error)) => {
+            warn_uncached(&cache_path, cache_error);
+            graph
+        }
+    }
+}
+
+/// Reports a callgraph that was loaded but could not be cached.
+fn warn_uncached(cache_path: &Path, cache_error: Option<io::Error>) {
+    if let Some(why) = cache_error {
+        eprintln!("Could not cache the callgraph in {}: {}", cache_path.display(), why);
+    }
+}
+
+fn run_inspect(sub: &ArgMatches) {
+    let name = sub.value_of("name").unwrap();
+    let version = sub.value_of("version").unwrap();
+    let graph = load_crate_graph(sub);
+
+    let packages: HashSet<(Option<Symbol>, Option<Symbol>)> = graph.nodes()
+        .filter(|n| graph.package_symbol(*n).is_some())
+        .map(|n| (graph.package_symbol(n), graph.version_symbol(n)))
+        .collect();
+    let local = graph.nodes().filter(|n| graph.package(*n) == Some(name)).count();
+    let std_funcs = graph.nodes().filter(|n| graph.package_symbol(*n).is_none()).count();
+    let memory = graph.memory_usage();
+
+    println!("Callgraph of {} - {}", name, version);
+    println!("Functions            = {}", graph.len());
+    println!("Calls                = {}", graph.call_count());
+    println!("Local functions      = {}", local);
+    println!("Std functions        = {}", std_funcs);
+    println!("Dependency functions = {}", graph.len() - local - std_funcs);
+    println!("Packages             = {}", packages.len());
+    println!("Interned strings     = {}", graph.strings().len());
+    println!("Memory as nodes      = {} (estimated)", format_bytes(graph.node_memory()));
+    println!("Memory compact       = {} (strings {}, functions {}, calls {})",
+        format_bytes(memory.total()), format_bytes(memory.strings), format_bytes(memory.nodes), format_bytes(memory.calls));
+}
+
+fn format_bytes(bytes: usize) -> String {
+    if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+fn run_explain(sub: &ArgMatches) {
+    let name = sub.value_of("name").unwrap();
+    let function = sub.value_of("function").unwrap();
+    let graph = load_crate_graph(sub);
+
+    // An exact def id wins over functions that merely contain it.
+    let mut candidates: Vec<usize> = graph.nodes().filter(|n| graph.def_id(*n) == function).collect();
+    if candidates.is_empty() {
+        candidates = graph.nodes().filter(|n| graph.def_id(*n).contains(function)).collect();
+    }
+    let target = match candidates.as_slice() {
+        [] => {
+            eprintln!("No function of the callgraph matches {}", function);
+            process::exit(exitcode::DATAERR);
+        },
+        [target] => *target,
+        _ => {
+            eprintln!("{} functions match {}, pick one of:", candidates.len(), function);
+            for n in candidates.iter().take(20) {
+                eprintln!("  {}", graph.def_id(*n));
+            }
+            process::exit(exitcode::DATAERR);
+        }
+    };
+
+    match shortest_call_path(&graph, name, target) {
+        None => println!("{} is not reachable from any function of {}", graph.def_id(target), name),
+        Some(path) => {
+            for (depth, id) in path.iter().enumerate() {
+                let package = match (graph.package(*id), graph.version(*id)) {
+                    (Some(package), Some(version)) => format!("{} {}", package, version),
+                    (Some(package), None) => package.to_string(),
+                    _ => "std".to_string()
+                };
+                println!("{}{}{}  ({})", "  ".repeat(depth), if depth == 0 { "" } else { "-> " }, graph.def_id(*id), package);
             }
         }
     }
@@ -0,0 +1,158 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::def_path::{crate_name_of, DefPath};
+use crate::sqlite_handler::SqliteHandler;
+
+/// Unreached functions that share a module path.
+#[derive(Serialize)]
+pub struct ModuleGroup {
+    pub module: String,
+    pub functions: Vec<String>
+}
+
+/// The public API of a crate that no analyzed dependent reaches.
+#[derive(Serialize)]
+pub struct DeadApiReport {
+    pub name: String,
+    pub version: Option<String>,
+    pub dependents: i64,
+    pub public_functions: usize,
+    pub unreached: usize,
+    pub modules: Vec<ModuleGroup>
+}
+
+/// Lists the externally visible functions of `name` that are never used by
+/// the analyzed crates depending on it, grouped by module. Functions of its
+/// own dependencies are left out, versions of the same function are merged.
+/// Crates analyzed before the database recorded visibility have to be
+/// analyzed again with `--force`.
+pub fn compute_dead_api(db_handler: &SqliteHandler, name: &str, version: Option<&str>) -> DeadApiReport {
+    let crate_name = crate_name_of(name);
+    let mut public: BTreeMap<String, (DefPath, i64)> = BTreeMap::new();
+    for (func, uses) in db_handler.get_dep_public_function_uses(name, version) {
+        let path = match DefPath::parse(&func) {
+            Some(path) if path.crate_name == crate_name => path,
+            _ => continue
+        };
+        public.entry(path.without_disambiguator()).or_insert((path, 0)).1 += uses;
+    }
+    let public_functions = public.len();
+
+    let mut modules: Vec<ModuleGroup> = Vec::new();
+    let mut unreached: Vec<(String, String)> = public.values()
+        .filter(|(_, uses)| *uses == 0)
+        .map(|(path, _)| (path.module_path(), path.to_string()))
+        .collect();
+    unreached.sort();
+    let unreached_count = unreached.len();
+    for (module, func) in unreached {
+        match modules.last_mut() {
+            Some(group) if group.module == module => group.functions.push(func),
+            _ => modules.push(ModuleGroup{ module, functions: vec![func] })
+        }
+    }
+
+    DeadApiReport {
+        name: name.to_string(),
+        version: version.map(String::from),
+        dependents: db_handler.count_dependents(name, version),
+        public_functions,
+        unreached: unreached_count,
+        modules
+    }
+}
+
+pub fn print_dead_api(report: &DeadApiReport) {
+    println!("Unreached public API of {} - {}", report.name, report.version.as_deref().unwrap_or("all versions"));
+    println!("Dependents       = {}", report.dependents);
+    println!("Public functions = {}", report.public_functions);
+    println!("Unreached        = {}", report.unreached);
+    for group in &report.modules {
+        println!();
+        println!("{} ({})", group.module, group.functions.len());
+        for func in &group.functions {
+            println!("  {}", func);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_calculator::{DepFunc, DepMetric, Metrics};
+
+    /// Functions as `(def_id, is_externally_visible)`.
+    fn dep_funcs(funcs: &[(&str, bool)]) -> Vec<DepFunc> {
+        funcs.iter().map(|(def_id, is_public)| DepFunc {
+            def_id: def_id.to_string(),
+            has_loc: true,
+            is_externally_visible: *is_public,
+            depth: None
+        }).collect()
+    }
+
+    /// Stores a crate that depends on `my-dep` and reaches the `used`
+    /// functions of its closure.
+    fn analyzed(db: &SqliteHandler, crate_name: &str, dep_version: &str, used: &[(&str, bool)], unused: &[(&str, bool)]) {
+        let dep = |funcs| vec![("my-dep".to_string(), dep_version.to_string(), funcs)];
+        let metrics = Metrics {
+            depMetrics: vec![DepMetric {
+                crate_name: "my-dep".to_string(),
+                crate_version: dep_version.to_string(),
+                usedCount: used.len(),
+                totalCount: used.len() + unused.len(),
+                total_loc: 0,
+                used_loc: 0,
+                total_count_with_LOC: 0,
+                used_count_with_LOC: 0
+            }],
+            used_funcs: dep(dep_funcs(used)),
+            unused_funcs: dep(dep_funcs(unused)),
+            ..Default::default()
+        };
+        db.insert_metric(&metrics, &crate_name.to_string(), &"0.1.0".to_string()).unwrap();
+    }
+
+    fn fixture() -> SqliteHandler {
+        let db = SqliteHandler::new(":memory:");
+        analyzed(&db, "a", "1.0.0", &[("my_dep[aaaa]::parse[0]", true)], &[
+            ("my_dep[aaaa]::top[0]", true),
+            ("my_dep[aaaa]::de[0]::read[0]", true),
+            ("my_dep[aaaa]::de[0]::{{impl}}[1]::next[0]", true),
+            ("my_dep[aaaa]::ser[0]::write[0]", true),
+            ("my_dep[aaaa]::util[0]::helper[0]", true),
+            ("my_dep[aaaa]::internal[0]", false),
+            ("other[cccc]::helper[0]", true)
+        ]);
+        analyzed(&db, "c", "1.1.0", &[("my_dep[bbbb]::ser[0]::write[0]", true)], &[]);
+        db
+    }
+
+    fn groups(report: &DeadApiReport) -> Vec<(&str, Vec<&str>)> {
+        report.modules.iter()
+            .map(|g| (g.module.as_str(), g.functions.iter().map(String::as_str).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn groups_unreached_public_functions_by_module() {
+        let report = compute_dead_api(&fixture(), "my-dep", None);
+        assert_eq!(report.dependents, 2);
+        assert_eq!(report.public_functions, 6);
+        assert_eq!(report.unreached, 4);
+        assert_eq!(groups(&report), vec![
+            ("my_dep", vec!["my_dep::top"]),
+            ("my_dep::de", vec!["my_dep::de::read", "my_dep::de::{impl#1}::next"]),
+            ("my_dep::util", vec!["my_dep::util::helper"])
+        ]);
+    }
+
+    #[test]
+    fn uses_of_other_versions_do_not_count_for_one_version() {
+        let report = compute_dead_api(&fixture(), "my-dep", Some("1.0.0"));
+        assert_eq!(report.dependents, 1);
+        assert_eq!(report.unreached, 5);
+        assert!(groups(&report).contains(&("my_dep::ser", vec!["my_dep::ser::write"])));
+    }
+}
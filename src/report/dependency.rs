@@ -0,0 +1,171 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::def_path::{crate_name_of, DefPath};
+use crate::report::ratio;
+use crate::sqlite_handler::SqliteHandler;
+
+#[derive(Serialize)]
+pub struct FunctionUse {
+    pub func: String,
+    pub use_count: i64,
+    pub has_loc: bool
+}
+
+/// How the analyzed crates use one dependency.
+#[derive(Serialize)]
+pub struct DependencyUsage {
+    pub name: String,
+    pub version: Option<String>,
+    pub dependents: i64,
+    pub total_functions: usize,
+    pub never_used: usize,
+    pub never_used_share: f64,
+    /// The most used functions, up to the requested limit.
+    pub functions: Vec<FunctionUse>
+}
+
+/// Collects the use counts of a dependency's functions, merging the versions
+/// of a function. The function lists of a direct dependency also contain the
+/// code of its own dependencies, which is left out unless
+/// `include_transitive` is set.
+pub fn compute_dependency_usage(db_handler: &SqliteHandler, name: &str, version: Option<&str>, include_transitive: bool, limit: usize) -> DependencyUsage {
+    let crate_name = crate_name_of(name);
+    let mut functions: Vec<FunctionUse> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (func, use_count, has_loc) in db_handler.get_dep_function_uses(name, version) {
+        let path = DefPath::parse(&func);
+        if !include_transitive && path.as_ref().map(|p| &p.crate_name) != Some(&crate_name) {
+            continue;
+        }
+        let func = path.map(|p| p.without_disambiguator()).unwrap_or(func);
+        let position = *positions.entry(func.to_string()).or_insert_with(|| {
+            functions.push(FunctionUse{ func, use_count: 0, has_loc: false });
+            functions.len() - 1
+        });
+        functions[position].use_count += use_count;
+        functions[position].has_loc |= has_loc;
+    }
+    functions.sort_by(|a, b| b.use_count.cmp(&a.use_count).then_with(|| a.func.cmp(&b.func)));
+    let never_used = functions.iter().filter(|f| f.use_count == 0).count();
+
+    DependencyUsage {
+        name: name.to_string(),
+        version: version.map(String::from),
+        dependents: db_handler.count_dependents(name, version),
+        total_functions: functions.len(),
+        never_used,
+        never_used_share: ratio(never_used, functions.len()),
+        functions: functions.into_iter().take(limit).collect()
+    }
+}
+
+pub fn print_dependency_usage(usage: &DependencyUsage) {
+    println!("Usage of {} - {}", usage.name, usage.version.as_deref().unwrap_or("all versions"));
+    println!("Dependents       = {}", usage.dependents);
+    println!("Functions        = {}", usage.total_functions);
+    println!("Never used       = {} ({:.4})", usage.never_used, usage.never_used_share);
+    if usage.functions.is_empty() {
+        return;
+    }
+    println!();
+    println!("{:>8}  Function", "Uses");
+    for f in &usage.functions {
+        let name = DefPath::parse(&f.func).map(|p| p.to_string()).unwrap_or_else(|| f.func.to_string());
+        println!("{:>8}  {}", f.use_count, name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_calculator::{DepFunc, DepMetric, Metrics};
+
+    fn dep_funcs(def_ids: &[&str]) -> Vec<DepFunc> {
+        def_ids.iter().map(|def_id| DepFunc {
+            def_id: def_id.to_string(),
+            has_loc: true,
+            is_externally_visible: true,
+            depth: None
+        }).collect()
+    }
+
+    /// Stores a crate that depends on `my-dep` and reaches the `used`
+    /// functions of its closure.
+    fn analyzed(db: &SqliteHandler, crate_name: &str, dep_version: &str, used: &[&str], unused: &[&str]) {
+        let dep = |funcs| vec![("my-dep".to_string(), dep_version.to_string(), funcs)];
+        let metrics = Metrics {
+            depMetrics: vec![DepMetric {
+                crate_name: "my-dep".to_string(),
+                crate_version: dep_version.to_string(),
+                usedCount: used.len(),
+                totalCount: used.len() + unused.len(),
+                total_loc: 0,
+                used_loc: 0,
+                total_count_with_LOC: 0,
+                used_count_with_LOC: 0
+            }],
+            used_funcs: dep(dep_funcs(used)),
+            unused_funcs: dep(dep_funcs(unused)),
+            ..Default::default()
+        };
+        db.insert_metric(&metrics, &crate_name.to_string(), &"0.1.0".to_string()).unwrap();
+    }
+
+    fn fixture() -> SqliteHandler {
+        let db = SqliteHandler::new(":memory:");
+        analyzed(&db, "a", "1.0.0",
+            &["my_dep[aaaa]::parse[0]", "my_dep[aaaa]::de[0]::read[0]"],
+            &["my_dep[aaaa]::unused[0]", "other[cccc]::helper[0]"]);
+        analyzed(&db, "b", "1.0.0", &["my_dep[aaaa]::parse[0]", "other[cccc]::helper[0]"], &[]);
+        analyzed(&db, "c", "1.1.0",
+            &["my_dep[bbbb]::parse[0]"],
+            &["my_dep[bbbb]::de[0]::read[0]", "my_dep[bbbb]::unused[0]"]);
+        db
+    }
+
+    fn ranking(usage: &DependencyUsage) -> Vec<(&str, i64)> {
+        usage.functions.iter().map(|f| (f.func.as_str(), f.use_count)).collect()
+    }
+
+    #[test]
+    fn ranks_functions_merged_over_versions() {
+        let usage = compute_dependency_usage(&fixture(), "my-dep", None, false, 10);
+        assert_eq!(ranking(&usage), vec![
+            ("my_dep::parse[0]", 3),
+            ("my_dep::de[0]::read[0]", 1),
+            ("my_dep::unused[0]", 0)
+        ]);
+        assert_eq!(usage.dependents, 3);
+        assert_eq!(usage.total_functions, 3);
+        assert_eq!(usage.never_used, 1);
+        assert!((usage.never_used_share - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn counts_a_single_version() {
+        let usage = compute_dependency_usage(&fixture(), "my-dep", Some("1.0.0"), false, 10);
+        assert_eq!(ranking(&usage), vec![
+            ("my_dep::parse[0]", 2),
+            ("my_dep::de[0]::read[0]", 1),
+            ("my_dep::unused[0]", 0)
+        ]);
+        assert_eq!(usage.dependents, 2);
+    }
+
+    #[test]
+    fn includes_transitive_functions_on_request() {
+        let usage = compute_dependency_usage(&fixture(), "my-dep", None, true, 10);
+        assert_eq!(usage.total_functions, 4);
+        assert_eq!(usage.functions.iter().find(|f| f.func == "other::helper[0]").map(|f| f.use_count), Some(1));
+        assert!((usage.never_used_share - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn limits_the_listed_functions_only() {
+        let usage = compute_dependency_usage(&fixture(), "my-dep", None, false, 1);
+        assert_eq!(ranking(&usage), vec![("my_dep::parse[0]", 3)]);
+        assert_eq!(usage.total_functions, 3);
+        assert_eq!(usage.never_used, 1);
+    }
+}
@@ -0,0 +1,327 @@
+use serde::Serialize;
+
+use crate::sqlite_handler::{MetricTotals, SqliteHandler};
+
+const HISTOGRAM_BINS: usize = 10;
+
+/// Summary statistics of an index over all crates or dependencies. The
+/// histogram splits [0, 1] into equally wide bins.
+#[derive(Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub std: f64,
+    pub p95: f64,
+    pub min: f64,
+    pub max: f64,
+    pub histogram: Vec<usize>
+}
+
+impl Distribution {
+    pub fn from_values(mut values: Vec<f64>) -> Distribution {
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = values.len();
+        let mean = if count == 0 { 0.0 } else { values.iter().sum::<f64>() / count as f64 };
+        // Population standard deviation, like numpy's default.
+        let variance = if count == 0 { 0.0 } else { values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count as f64 };
+
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        for v in &values {
+            let bin = ((v * HISTOGRAM_BINS as f64) as usize).min(HISTOGRAM_BINS - 1);
+            histogram[bin] += 1;
+        }
+
+        Distribution {
+            count,
+            mean,
+            median: percentile(&values, 50.0),
+            std: variance.sqrt(),
+            p95: percentile(&values, 95.0),
+            min: values.first().cloned().unwrap_or(0.0),
+            max: values.last().cloned().unwrap_or(0.0),
+            histogram
+        }
+    }
+
+    fn share_below(values: &[f64], limit: f64) -> f64 {
+        if values.is_empty() {
+            return 0.0;
+        }
+        values.iter().filter(|v| **v < limit).count() as f64 / values.len() as f64
+    }
+}
+
+/// Percentile of sorted values with linear interpolation, like numpy's default.
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = percent / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Used dependency functions over all dependency functions, per crate.
+#[derive(Serialize)]
+pub struct LeannessReport {
+    /// Over crates that use some of their dependency functions.
+    pub nodes: Distribution,
+    /// Over crates that also use some of their dependency LOC, like `loc`.
+    pub nodes_with_loc: Distribution,
+    pub loc: Distribution,
+    pub nodes_below_5: f64,
+    pub loc_below_5: f64,
+    pub nodes_below_25: f64,
+    pub loc_below_25: f64,
+    /// Crates with dependency functions of which none is used.
+    pub zero_usage: usize
+}
+
+/// Dependency LOC over all LOC, per crate.
+#[derive(Serialize)]
+pub struct DependencyReport {
+    /// Over all crates with code, those without dependency code included.
+    pub distribution: Distribution,
+    /// Over the crates that have dependency code, for the mean and spread.
+    pub with_dependency_code: Distribution,
+    /// Crates without any dependency code, as share of all crates.
+    pub zero_share: f64,
+    pub bottom: usize,
+    pub middle: usize,
+    pub top: usize
+}
+
+/// Used functions over all functions, per dependency.
+#[derive(Serialize)]
+pub struct UtilizationReport {
+    /// Summed over the `dep_metrics` rows of dependencies with enough dependents.
+    pub per_dependent: Distribution,
+    pub per_dependent_below_10: f64,
+    pub most_dependents: Option<(String, String, i64)>,
+    /// From the cumulative use counts in `dep_func_metrics`.
+    pub per_function: Distribution,
+    pub per_function_below_10: f64
+}
+
+#[derive(Serialize)]
+pub struct IndexReport {
+    pub leanness: LeannessReport,
+    pub dependency: DependencyReport,
+    pub utilization: UtilizationReport
+}
+
+/// Computes the indices histogramer.py derives from the `metrics`,
+/// `dep_metrics` and `dep_func_metrics` tables.
+pub fn compute_indices(db_handler: &SqliteHandler, min_dependents: u32) -> IndexReport {
+    let totals = db_handler.get_metric_totals();
+
+    let utilization_rows = db_handler.get_dep_utilization(min_dependents);
+    let per_dependent: Vec<f64> = utilization_rows.iter()
+        .filter(|r| r.3 > 0 && r.4 > 0)
+        .map(|r| r.3 as f64 / r.4 as f64)
+        .collect();
+
+    let per_function: Vec<f64> = db_handler.get_dep_function_usage().iter()
+        .filter(|(used, _)| *used > 0)
+        .map(|(used, unused)| *used as f64 / (used + unused) as f64)
+        .collect();
+
+    IndexReport {
+        leanness: leanness(&totals),
+        dependency: dependency(&totals),
+        utilization: UtilizationReport {
+            per_dependent_below_10: Distribution::share_below(&per_dependent, 0.10),
+            per_dependent: Distribution::from_values(per_dependent),
+            most_dependents: most_dependents(&utilization_rows),
+            per_function_below_10: Distribution::share_below(&per_function, 0.10),
+            per_function: Distribution::from_values(per_function)
+        }
+    }
+}
+
+/// `graph_leanness` takes every crate that uses some dependency function,
+/// `graph_leanness_loc` only those that also use some dependency LOC.
+fn leanness(totals: &[MetricTotals]) -> LeannessReport {
+    let nodes: Vec<f64> = totals.iter()
+        .filter(|t| t.total_dep_func_count > 0 && t.used_dep_func_count > 0)
+        .map(|t| t.used_dep_func_count as f64 / t.total_dep_func_count as f64)
+        .collect();
+
+    let mut nodes_with_loc = Vec::new();
+    let mut loc = Vec::new();
+    for t in totals {
+        if t.total_dep_func_count > 0 && t.used_dep_func_count > 0 && t.total_dep_loc > 0 && t.used_dep_loc > 0 {
+            nodes_with_loc.push(t.used_dep_func_count as f64 / t.total_dep_func_count as f64);
+            loc.push(t.used_dep_loc as f64 / t.total_dep_loc as f64);
+        }
+    }
+
+    LeannessReport {
+        nodes_below_5: Distribution::share_below(&nodes_with_loc, 0.05),
+        loc_below_5: Distribution::share_below(&loc, 0.05),
+        nodes_below_25: Distribution::share_below(&nodes_with_loc, 0.25),
+        loc_below_25: Distribution::share_below(&loc, 0.25),
+        nodes: Distribution::from_values(nodes),
+        nodes_with_loc: Distribution::from_values(nodes_with_loc),
+        loc: Distribution::from_values(loc),
+        zero_usage: totals.iter().filter(|t| t.total_dep_func_count > 0 && t.used_dep_func_count == 0).count()
+    }
+}
+
+fn dependency(totals: &[MetricTotals]) -> DependencyReport {
+    let dependency: Vec<f64> = totals.iter()
+        .filter(|t| t.total_loc > 0)
+        .map(|t| t.total_dep_loc as f64 / t.total_loc as f64)
+        .collect();
+    let zero_count = dependency.iter().filter(|d| **d == 0.0).count();
+
+    DependencyReport {
+        zero_share: if dependency.is_empty() { 0.0 } else { zero_count as f64 / dependency.len() as f64 },
+        bottom: zero_count,
+        middle: dependency.iter().filter(|d| **d > 0.0 && **d < 0.9).count(),
+        top: dependency.iter().filter(|d| **d >= 0.9).count(),
+        with_dependency_code: Distribution::from_values(dependency.iter().cloned().filter(|d| *d > 0.0).collect()),
+        distribution: Distribution::from_values(dependency)
+    }
+}
+
+/// The first dependency with the highest dependent count, like the strict
+/// comparison in `graph_utilization_index`.
+fn most_dependents(rows: &[(String, String, i64, i64, i64)]) -> Option<(String, String, i64)> {
+    let mut most: Option<&(String, String, i64, i64, i64)> = None;
+    for row in rows {
+        match most {
+            Some(m) if m.2 >= row.2 => {},
+            _ => most = Some(row)
+        }
+    }
+    most.map(|r| (r.0.to_string(), r.1.to_string(), r.2))
+}
+
+fn print_distribution(name: &str, distribution: &Distribution) {
+    println!("{}", name);
+    println!("  count   = {}", distribution.count);
+    println!("  median  = {:.4}", distribution.median);
+    println!("  mean    = {:.4}", distribution.mean);
+    println!("  std     = {:.4}", distribution.std);
+    println!("  95th    = {:.4}", distribution.p95);
+    println!("  range   = {:.4} - {:.4}", distribution.min, distribution.max);
+    let bins: Vec<String> = distribution.histogram.iter().map(|b| b.to_string()).collect();
+    println!("  bins    = [{}]", bins.join(", "));
+}
+
+pub fn print_indices(report: &IndexReport) {
+    print_distribution("Leanness index (nodes)", &report.leanness.nodes);
+    print_distribution("Leanness index (nodes, crates using dependency LOC)", &report.leanness.nodes_with_loc);
+    print_distribution("Leanness index (LOC)", &report.leanness.loc);
+    println!("Lean less than 5% (NCO)  = {:.4}", report.leanness.nodes_below_5);
+    println!("Lean less than 5% (LOC)  = {:.4}", report.leanness.loc_below_5);
+    println!("Lean less than 25% (NCO) = {:.4}", report.leanness.nodes_below_25);
+    println!("Lean less than 25% (LOC) = {:.4}", report.leanness.loc_below_25);
+    println!("Crates using no dependency function = {}", report.leanness.zero_usage);
+    println!();
+
+    print_distribution("Dependency index", &report.dependency.distribution);
+    print_distribution("Dependency index (crates with dependency code)", &report.dependency.with_dependency_code);
+    println!("0 LOC in dependencies    = {:.4}", report.dependency.zero_share);
+    println!("Comp analysis. Bottom    = {}", report.dependency.bottom);
+    println!("Comp analysis. Middle    = {}", report.dependency.middle);
+    println!("Comp analysis. Top       = {}", report.dependency.top);
+    println!();
+
+    print_distribution("Utilization index (per dependency)", &report.utilization.per_dependent);
+    println!("Utilization less than 10% = {:.4}", report.utilization.per_dependent_below_10);
+    if let Some((name, version, dependents)) = &report.utilization.most_dependents {
+        println!("Highest dependent count   = {} - {} ({})", name, version, dependents);
+    }
+    print_distribution("Utilization index (per function use counts)", &report.utilization.per_function);
+    println!("Utilization less than 10% = {:.4}", report.utilization.per_function_below_10);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() < 1e-12
+    }
+
+    fn totals(total_dep_func_count: i64, used_dep_func_count: i64, total_dep_loc: i64, used_dep_loc: i64, total_loc: i64) -> MetricTotals {
+        MetricTotals { total_dep_func_count, used_dep_func_count, total_dep_loc, used_dep_loc, total_loc }
+    }
+
+    // Expected values are what np.percentile (linear interpolation), np.mean
+    // and np.std (ddof=0) give for the same inputs.
+    #[test]
+    fn percentile_interpolates_linearly() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert!(close(percentile(&sorted, 95.0), 3.85));
+        assert!(close(percentile(&sorted, 50.0), 2.5));
+        assert!(close(percentile(&sorted, 0.0), 1.0));
+        assert!(close(percentile(&sorted, 100.0), 4.0));
+        assert!(close(percentile(&[7.0], 95.0), 7.0));
+        assert!(close(percentile(&[], 95.0), 0.0));
+    }
+
+    #[test]
+    fn distribution_uses_population_std() {
+        let distribution = Distribution::from_values(vec![0.9, 0.1, 0.5, 0.3]);
+        assert_eq!(distribution.count, 4);
+        assert!(close(distribution.mean, 0.45));
+        assert!(close(distribution.median, 0.4));
+        assert!(close(distribution.std, 0.295_803_989_154_980_8));
+        assert!(close(distribution.p95, 0.84));
+        assert!(close(distribution.min, 0.1));
+        assert!(close(distribution.max, 0.9));
+    }
+
+    #[test]
+    fn histogram_puts_one_in_the_last_bin() {
+        let distribution = Distribution::from_values(vec![0.0, 0.15, 0.35, 1.0]);
+        assert_eq!(distribution.histogram, vec![1, 1, 0, 1, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn node_leanness_only_needs_used_functions() {
+        let report = leanness(&[
+            totals(10, 5, 100, 20, 200),
+            totals(10, 1, 0, 0, 50),
+            totals(10, 0, 100, 0, 100)
+        ]);
+        assert_eq!(report.nodes.count, 2);
+        assert!(close(report.nodes.mean, 0.3));
+        assert_eq!(report.nodes_with_loc.count, 1);
+        assert!(close(report.nodes_with_loc.mean, 0.5));
+        assert_eq!(report.loc.count, 1);
+        assert!(close(report.loc.mean, 0.2));
+        assert!(close(report.loc_below_25, 1.0));
+        assert!(close(report.nodes_below_25, 0.0));
+        assert_eq!(report.zero_usage, 1);
+    }
+
+    #[test]
+    fn dependency_percentile_includes_crates_without_dependency_code() {
+        let report = dependency(&[
+            totals(10, 5, 100, 20, 200),
+            totals(0, 0, 0, 0, 50),
+            totals(10, 5, 100, 20, 100),
+            totals(0, 0, 0, 0, 0)
+        ]);
+        assert_eq!(report.distribution.count, 3);
+        assert!(close(report.distribution.p95, 0.95));
+        assert!(close(report.with_dependency_code.mean, 0.75));
+        assert!(close(report.with_dependency_code.std, 0.25));
+        assert!(close(report.zero_share, 1.0 / 3.0));
+        assert_eq!((report.bottom, report.middle, report.top), (1, 1, 1));
+    }
+
+    #[test]
+    fn most_dependents_keeps_the_first_of_a_tie() {
+        let row = |name: &str, dependents| (name.to_string(), "1.0.0".to_string(), dependents, 1, 2);
+        let most = most_dependents(&[row("a", 6), row("b", 7), row("c", 7)]);
+        assert_eq!(most, Some(("b".to_string(), "1.0.0".to_string(), 7)));
+        assert_eq!(most_dependents(&[]), None);
+    }
+}
@@ -0,0 +1,127 @@
+pub mod dead_api;
+pub mod dependency;
+pub mod indices;
+
+use std::cmp::Reverse;
+
+use crate::features::{Suggestion, SuggestionKind};
+use crate::index_calculator::{Metrics, PathKind};
+use crate::sqlite_handler::SqliteHandler;
+
+/// `part / total`, or 0 for an empty total.
+pub fn ratio(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+/// Prints the metrics of one crate and a line per direct dependency.
+pub fn print_metrics(metrics: &Metrics, crate_name: &str, crate_version: &str){
+    let non_std_count = metrics.TotalFuncCount - metrics.StdFuncCount;
+
+    println!("Metrics of {} - {}", crate_name, crate_version);
+    println!("Total func count     = {}", metrics.TotalFuncCount);
+    println!("Local func count     = {}", metrics.LocalFuncCount);
+    println!("Std func count       = {}", metrics.StdFuncCount);
+    println!("Total dep func count = {}", metrics.TotalDepFuncCount);
+    println!("Used dep func count  = {}", metrics.UsedDepFuncCount);
+    println!("Total dep LOC        = {}", metrics.TotalDepLOC);
+    println!("Used dep LOC         = {}", metrics.UsedDepLOC);
+    println!("Directly called deps = {}", metrics.direct_dep_func_count);
+    println!("Dep depth (max/mean) = {} / {:.2}", metrics.max_dep_depth, metrics.mean_dep_depth);
+    println!("Own code share       = {:.4}", ratio(metrics.LocalFuncCount, non_std_count));
+    println!("Leanness index (n)   = {:.4}", ratio(metrics.UsedDepFuncCount, metrics.TotalDepFuncCount));
+    println!("Leanness index (l)   = {:.4}", ratio(metrics.UsedDepLOC, metrics.TotalDepLOC));
+    println!("Public leanness (l)  = {:.4}", ratio(metrics.UsedDepPublicLOC, metrics.TotalDepPublicLOC));
+    println!("Dependency index (n) = {:.4}", ratio(metrics.TotalDepFuncCount, metrics.TotalFuncCount));
+    println!("Dependency index (l) = {:.4}", ratio(metrics.TotalDepLOC, metrics.TotalLOC));
+
+    if metrics.depMetrics.is_empty() {
+        return;
+    }
+    println!();
+    println!("{:<30} {:<12} {:>13} {:>9} {:>15} {:>9}", "Dependency", "Version", "Used funcs", "Lean (n)", "Used LOC", "Lean (l)");
+    for dep in &metrics.depMetrics {
+        println!("{:<30} {:<12} {:>13} {:>9.4} {:>15} {:>9.4}",
+            dep.crate_name,
+            dep.crate_version,
+            format!("{}/{}", dep.usedCount, dep.totalCount),
+            ratio(dep.usedCount, dep.totalCount),
+            format!("{}/{}", dep.used_loc, dep.total_loc),
+            ratio(dep.used_loc, dep.total_loc));
+    }
+
+    println!();
+    println!("{:<30} {:<40} {:>13} {:>9} {:>15} {:>9}", "Reached through", "Module", "Used funcs", "Lean (n)", "Used LOC", "Lean (l)");
+    for path in metrics.path_metrics.iter().filter(|p| p.kind == PathKind::Module) {
+        println!("{:<30} {:<40} {:>13} {:>9.4} {:>15} {:>9.4}",
+            path.dep_name,
+            path.path,
+            format!("{}/{}", path.used_count, path.total_count),
+            ratio(path.used_count, path.total_count),
+            format!("{}/{}", path.used_loc, path.total_loc),
+            ratio(path.used_loc, path.total_loc));
+    }
+
+    // Largest sources of unused code first.
+    let mut bloat: Vec<_> = metrics.bloat.iter().collect();
+    bloat.sort_by_key(|dep| Reverse(dep.unused_loc));
+    println!();
+    println!("{:<30} {:<12} {:>8} {:>13} {:>11} {:>16} {:>14}", "Unused code pulled in by", "Version", "Packages", "Unused funcs", "Unused LOC", "Exclusive funcs", "Exclusive LOC");
+    for dep in bloat {
+        println!("{:<30} {:<12} {:>8} {:>13} {:>11} {:>16} {:>14}",
+            dep.name,
+            dep.version,
+            dep.package_count,
+            dep.unused_count,
+            dep.unused_loc,
+            dep.exclusive_unused_count,
+            dep.exclusive_unused_loc);
+    }
+}
+
+/// Prints the dependencies and dependency features the analyzed crate could
+/// turn off.
+pub fn print_suggestions(suggestions: &[Suggestion]){
+    if suggestions.is_empty() {
+        println!("Every dependency declared in Cargo.toml is used.");
+        return;
+    }
+    for suggestion in suggestions {
+        let size = format!("{} functions, {} LOC", suggestion.functions, suggestion.loc);
+        match suggestion.kind {
+            SuggestionKind::DisableOptional if suggestion.features.is_empty() =>
+                println!("Optional {} - {} is unused ({})", suggestion.dependency, suggestion.version, size),
+            SuggestionKind::DisableOptional =>
+                println!("Optional {} - {} is unused, consider turning off feature(s) {} ({})",
+                    suggestion.dependency, suggestion.version, suggestion.features.join(", "), size),
+            SuggestionKind::Unused =>
+                println!("{} - {} is unused, consider removing it or making it optional ({})", suggestion.dependency, suggestion.version, size),
+            SuggestionKind::DisableFeature =>
+                println!("{} - {} builds unused {}, consider turning off its feature(s) {} ({})",
+                    suggestion.dependency, suggestion.version, suggestion.optional_dependency.as_deref().unwrap_or_default(),
+                    suggestion.features.join(", "), size)
+        }
+    }
+}
+
+/// Prints how many crate versions failed per error kind and pipeline stage.
+pub fn print_error_summary(db_handler: &SqliteHandler){
+    let summary = db_handler.get_error_summary();
+    let total: i64 = summary.iter().map(|s| s.2).sum();
+
+    println!("{:<30} {:<12} {:>8}", "Kind", "Stage", "Failures");
+    for (kind, stage, count) in &summary {
+        println!("{:<30} {:<12} {:>8}", kind, stage, count);
+    }
+    println!("{:<30} {:<12} {:>8}", "Total", "", total);
+}
+
+/// Prints the latest failures of one error kind with their full messages.
+pub fn print_errors_of_kind(db_handler: &SqliteHandler, kind: &str, limit: u32){
+    for (name, version, message) in db_handler.get_errors_of_kind(kind, limit) {
+        println!("{} - {}: {}", name, version, message);
+    }
+}
@@ -1,11 +1,27 @@
 use rusqlite::{params, Connection, Result};
 use rusqlite::NO_PARAMS;
+use std::collections::{HashMap, HashSet};
 
 // use chrono::prelude::*;
 
+use crate::errors::AnalysisError;
 use crate::index_calculator::Metrics;
 
-#[derive(PartialEq)]
+/// Table and columns Präzi keeps its per-crate progress in, one row per crate
+/// version with the state stored as a `MainCrateState` number. This layout is
+/// assumed rather than taken from Präzi, so it is checked when the database is
+/// opened and a table without these columns is treated like a missing one.
+/// The analyzer only reads this table and keeps its own progress in
+/// `analysis_state`.
+const CRATE_STATE_TABLE: &str = "crates";
+const CRATE_STATE_NAME: &str = "name";
+const CRATE_STATE_VERSION: &str = "version";
+const CRATE_STATE_COLUMN: &str = "state";
+
+/// Progress of a crate version through Präzi and then through the analyzer.
+/// Präzi's states end at `FullCallgraph`, the rest are only ever written to
+/// `analysis_state`.
+#[derive(PartialEq, Clone, Copy, Debug)]
 #[repr(u8)]
 pub enum MainCrateState{
     Unknown = 0,
@@ -14,6 +30,10 @@ pub enum MainCrateState{
     Built = 3,
     BaseCallgraph = 4,
     FullCallgraph = 5,
+    Analyzed = 6,
+    AnalysisFailed = 7,
+    /// The callgraph was over the maximum graph size and was not analyzed.
+    SkippedTooLarge = 8,
 }
 
 impl MainCrateState {
@@ -25,13 +45,74 @@ impl MainCrateState {
             3 => MainCrateState::Built,
             4 => MainCrateState::BaseCallgraph,
             5 => MainCrateState::FullCallgraph,
+            6 => MainCrateState::Analyzed,
+            7 => MainCrateState::AnalysisFailed,
+            8 => MainCrateState::SkippedTooLarge,
             _ => MainCrateState::Unknown,
         }
     }
+
+    /// Whether Präzi finished building the full callgraph, which the
+    /// analyzer needs.
+    pub fn has_full_callgraph(self) -> bool {
+        self as u8 >= MainCrateState::FullCallgraph as u8
+    }
+}
+
+/// Column names of a table, none if it does not exist.
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>>{
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map(NO_PARAMS, |row| row.get(1))?;
+    columns.collect()
+}
+
+/// Whether the database has Präzi's crate progress table with the columns
+/// the analyzer reads.
+fn has_crate_state_table(conn: &Connection) -> bool{
+    match table_columns(conn, CRATE_STATE_TABLE) {
+        Err(why) => {
+            println!("Could not read the columns of {}: {}", CRATE_STATE_TABLE, why);
+            false
+        },
+        Ok(columns) if columns.is_empty() => false,
+        Ok(columns) => {
+            let missing: Vec<&str> = [CRATE_STATE_NAME, CRATE_STATE_VERSION, CRATE_STATE_COLUMN].iter()
+                .filter(|column| !columns.iter().any(|c| c == *column))
+                .cloned()
+                .collect();
+            if !missing.is_empty() {
+                println!("Ignoring Präzi states, {} has no {} column(s)", CRATE_STATE_TABLE, missing.join(", "));
+            }
+            missing.is_empty()
+        }
+    }
+}
+
+/// Adds a column to a table created by an older version of the analyzer.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str){
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+    let columns: Vec<String> = stmt.query_map(NO_PARAMS, |row| row.get(1)).unwrap()
+        .filter_map(|c| c.ok())
+        .collect();
+    if !columns.iter().any(|c| c == column) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), NO_PARAMS).unwrap();
+    }
+}
+
+/// The columns of a `metrics` row the derived indices are computed from.
+pub struct MetricTotals {
+    pub total_dep_func_count: i64,
+    pub used_dep_func_count: i64,
+    pub total_dep_loc: i64,
+    pub used_dep_loc: i64,
+    pub total_loc: i64
 }
 
 pub struct SqliteHandler{
-    conn: rusqlite::Connection
+    conn: rusqlite::Connection,
+    /// Whether the database has Präzi's crate progress table with the expected
+    /// columns, checked once when opening it.
+    has_crate_states: bool
 }
 
 impl SqliteHandler{
@@ -63,11 +144,18 @@ impl SqliteHandler{
                 total_dep_func_count_with_LOC INT,
                 used_dep_func_count_with_LOC INT,
                 total_dep_public_func_count_with_LOC INT,
-                used_dep_public_func_count_with_LOC INT
+                used_dep_public_func_count_with_LOC INT,
+                direct_dep_func_count INT,
+                max_dep_depth INT,
+                mean_dep_depth REAL
             )",
             NO_PARAMS,
         ).unwrap();
 
+        add_column_if_missing(&conn, "metrics", "direct_dep_func_count", "INT");
+        add_column_if_missing(&conn, "metrics", "max_dep_depth", "INT");
+        add_column_if_missing(&conn, "metrics", "mean_dep_depth", "REAL");
+
         conn.execute(
             "CREATE TABLE IF NOT EXISTS dep_metrics (
                 name VARCHAR(100) NOT NULL,
@@ -84,6 +172,22 @@ impl SqliteHandler{
             NO_PARAMS,
         ).unwrap();
 
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dep_path_metrics (
+                crate_id INT NOT NULL,
+                dep_name VARCHAR(100) NOT NULL,
+                dep_version VARCHAR(100) NOT NULL,
+                kind VARCHAR(16) NOT NULL,
+                path TEXT NOT NULL,
+                total_count INT NOT NULL,
+                used_count INT NOT NULL,
+                total_LOC INT NOT NULL,
+                used_LOC INT NOT NULL,
+                FOREIGN KEY(crate_id) REFERENCES metrics(id)
+            )",
+            NO_PARAMS,
+        ).unwrap();
+
         conn.execute(
             "CREATE TABLE IF NOT EXISTS dep (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -99,12 +203,15 @@ impl SqliteHandler{
                 use_count INT NOT NULL,
                 dep_id INT NOT NULL,
                 has_LOC INT NOT NULL,
+                is_public INT NOT NULL DEFAULT 0,
                 FOREIGN KEY(dep_id) REFERENCES dep(id),
                 UNIQUE(func, dep_id)
             );",
             NO_PARAMS,
         ).unwrap();
 
+        add_column_if_missing(&conn, "dep_func_metrics", "is_public", "INT NOT NULL DEFAULT 0");
+
         conn.execute(
             "CREATE UNIQUE INDEX IF NOT EXISTS unique_dfm 
             ON dep_func_metrics (func, dep_id);"
@@ -115,17 +222,53 @@ impl SqliteHandler{
             ON dep_func_metrics (dep_id, use_count);"
         , NO_PARAMS).unwrap();
 
+        // The crate version behind every `use_count` increment, so replacing a
+        // crate's rows can take its uses back.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dep_func_uses (
+                crate_id INT NOT NULL,
+                func_id INT NOT NULL,
+                FOREIGN KEY(crate_id) REFERENCES metrics(id)
+            )",
+            NO_PARAMS,
+        ).unwrap();
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS dfu_crate
+            ON dep_func_uses (crate_id);"
+        , NO_PARAMS).unwrap();
+
         conn.execute(
             "CREATE TABLE IF NOT EXISTS metric_errors (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 name VARCHAR(100) NOT NULL,
                 version VARCHAR(100) NOT NULL,
-                error VARCHAR(256) NOT NULL
+                error VARCHAR(256) NOT NULL,
+                kind VARCHAR(64),
+                stage VARCHAR(32),
+                message TEXT,
+                created_at DATETIME
+            )",
+            NO_PARAMS,
+        ).unwrap();
+        add_column_if_missing(&conn, "metric_errors", "kind", "VARCHAR(64)");
+        add_column_if_missing(&conn, "metric_errors", "stage", "VARCHAR(32)");
+        add_column_if_missing(&conn, "metric_errors", "message", "TEXT");
+        add_column_if_missing(&conn, "metric_errors", "created_at", "DATETIME");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS analysis_state (
+                name VARCHAR(100) NOT NULL,
+                version VARCHAR(100) NOT NULL,
+                state INT NOT NULL,
+                updated_at DATETIME,
+                PRIMARY KEY(name, version)
             )",
             NO_PARAMS,
         ).unwrap();
 
-        SqliteHandler { conn : conn }
+        let has_crate_states = has_crate_state_table(&conn);
+        SqliteHandler { conn, has_crate_states }
     }
 
     pub fn get_dep_id(&self, name: &String, version: &String) -> i64{
@@ -161,17 +304,19 @@ impl SqliteHandler{
         }
     }
 
-    pub fn insert_unused_func(&self, id: &i64, func_name: &String, has_LOC: bool){
+    pub fn insert_unused_func(&self, id: &i64, func_name: &String, has_LOC: bool, is_public: bool){
         let result = self.conn.execute(
             "INSERT INTO dep_func_metrics (
                 dep_id, 
                 func, 
                 use_count,
-                has_LOC)
-                VALUES(?1, ?2, 0, ?3)",
+                has_LOC,
+                is_public)
+                VALUES(?1, ?2, 0, ?3, ?4)",
             params![id,
                 func_name,
-                has_LOC]
+                has_LOC,
+                is_public]
         );
         match result {
             Err(_why) => return,
@@ -216,60 +361,329 @@ impl SqliteHandler{
             Ok(_) => ()
         }
     }
+
+    pub fn rollback_transaction(&self){
+        match self.conn.execute("ROLLBACK", NO_PARAMS){
+            Err(why) => {
+                println!("{:?}", why);
+            },
+            Ok(_) => ()
+        }
+    }
     
-    pub fn update_or_insert_func(&self, id: &i64, func_name: &String, has_LOC: bool){
-        let result = self.conn.execute(
+    /// Counts a use of a function by the crate stored as `crate_id`.
+    pub fn update_or_insert_func(&self, crate_id: i64, id: &i64, func_name: &String, has_LOC: bool, is_public: bool) -> Result<()>{
+        // Rows written before `is_public` existed pick the flag up here.
+        let updated = self.conn.execute(
             "UPDATE dep_func_metrics 
             SET 
-                use_count = use_count + 1
+                use_count = use_count + 1,
+                is_public = MAX(is_public, ?3)
             WHERE
                 dep_id = ?1 AND func = ?2",
             params![id, 
-                func_name]
-        );
-        match result {
-            Err(why) => {
-                println!("{:?}", why)
-            },
-            Ok(val) => {
-                if val == 0 {
-                    let result = self.conn.execute(
-                        "INSERT INTO dep_func_metrics (
-                            dep_id, 
-                            func, 
-                            use_count,
-                            has_LOC)
-                            VALUES(?1, ?2, 1, ?3)",
-                        params![id, func_name, has_LOC]
-                    );
-                    match result {
-                        Err(why) => println!("{:?}", why),
-                        Ok(_val) => return
-                    }
-                }
-            }
+                func_name,
+                is_public]
+        )?;
+        if updated == 0 {
+            self.conn.execute(
+                "INSERT INTO dep_func_metrics (
+                    dep_id, 
+                    func, 
+                    use_count,
+                    has_LOC,
+                    is_public)
+                    VALUES(?1, ?2, 1, ?3, ?4)",
+                params![id, func_name, has_LOC, is_public]
+            )?;
         }
+        self.conn.execute(
+            "INSERT INTO dep_func_uses (crate_id, func_id)
+                SELECT ?1, rowid FROM dep_func_metrics WHERE dep_id = ?2 AND func = ?3",
+            params![crate_id, id, func_name]
+        )?;
+        Ok(())
     }
 
-    pub fn insert_error(&self, error: String, crate_name: &String, crate_version: &String){
-        let result = self.conn.execute(
+    pub fn insert_error(&self, error: &AnalysisError, crate_name: &String, crate_version: &String){
+        if let Err(why) = self.insert_error_row(error, crate_name, crate_version) {
+            println!("{:?}", why);
+        }
+    }
+
+    fn insert_error_row(&self, error: &AnalysisError, crate_name: &String, crate_version: &String) -> Result<()>{
+        self.conn.execute(
             "INSERT INTO metric_errors (
                 name, 
                 version, 
-                error)
-                VALUES(?1, ?2, ?3)",
+                error,
+                kind,
+                stage,
+                message,
+                created_at)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, datetime('now'))",
             params![crate_name,
                 crate_version,
-                error]
-        );
+                error.to_string(),
+                error.kind(),
+                error.stage().as_str(),
+                format!("{:?}", error)]
+        )?;
+        Ok(())
+    }
+    
+    pub fn insert_metric(&self, metrics: &Metrics, crate_name: &String, crate_version: &String) -> Result<()>{
+        self.begin_transaction();
+        let result = self.insert_metric_rows(metrics, crate_name, crate_version);
+        self.finish_transaction(result)
+    }
+
+    /// Replaces everything stored for a crate version, the function uses it
+    /// counted included, with the new metrics in one transaction.
+    pub fn replace_metric(&self, metrics: &Metrics, crate_name: &String, crate_version: &String) -> Result<()>{
+        self.begin_transaction();
+        let result = self.delete_crate_rows(crate_name, crate_version)
+            .and_then(|_| self.insert_metric_rows(metrics, crate_name, crate_version));
+        self.finish_transaction(result)
+    }
+
+    fn finish_transaction(&self, result: Result<()>) -> Result<()>{
         match result {
-            Err(why) => println!("{:?}", why),
-            Ok(_val) => return
+            Err(why) => {
+                self.rollback_transaction();
+                Err(why)
+            },
+            Ok(_) => {
+                self.end_transaction();
+                Ok(())
+            }
         }
     }
-    
-    pub fn insert_metric(&self, metrics: &Metrics, crate_name: &String, crate_version: &String){
-        let result = self.conn.execute(
+
+    /// Whether the database has Präzi's crate progress table.
+    pub fn has_crate_states(&self) -> bool{
+        self.has_crate_states
+    }
+
+    /// The Präzi state of every crate version it knows about.
+    pub fn get_crate_states(&self) -> Result<HashMap<(String, String), MainCrateState>>{
+        let sql = format!("SELECT {}, {}, {} FROM {}", CRATE_STATE_NAME, CRATE_STATE_VERSION, CRATE_STATE_COLUMN, CRATE_STATE_TABLE);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let states = stmt.query_map(NO_PARAMS, |row| {
+            let state: i64 = row.get(2)?;
+            Ok(((row.get(0)?, row.get(1)?), MainCrateState::from(state as u8)))
+        })?;
+        states.collect()
+    }
+
+    /// Records the analyzer's progress for a crate version in
+    /// `analysis_state`, replacing the state of an earlier run.
+    pub fn set_analysis_state(&self, crate_name: &String, crate_version: &String, state: MainCrateState){
+        if let Err(why) = self.conn.execute(
+            "INSERT OR REPLACE INTO analysis_state (name, version, state, updated_at)
+            VALUES (?1, ?2, ?3, datetime('now'))",
+            params![crate_name, crate_version, state as u8]
+        ) {
+            println!("{:?}", why);
+        }
+    }
+
+    /// Number of failed crate versions per error kind and stage, most frequent
+    /// first. Only the latest error of a version counts, and versions that have
+    /// metrics by now don't count at all.
+    pub fn get_error_summary(&self) -> Vec<(String, String, i64)>{
+        let sql = "SELECT IFNULL(kind, 'unknown'), IFNULL(stage, 'unknown'), COUNT(*) AS failures
+            FROM metric_errors
+            WHERE id IN (SELECT MAX(id) FROM metric_errors GROUP BY name, version)
+            AND NOT EXISTS (SELECT 1 FROM metrics
+                WHERE crate_name = metric_errors.name AND crate_version = metric_errors.version)
+            GROUP BY 1, 2
+            ORDER BY failures DESC";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let res = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)));
+
+        match res {
+            Err(_why) => Vec::new(),
+            Ok(val) => val.filter_map(|x| x.ok()).collect()
+        }
+    }
+
+    pub fn get_metric_totals(&self) -> Vec<MetricTotals>{
+        let sql = "SELECT total_dep_func_count, used_dep_func_count, total_dep_LOC, used_dep_LOC, total_LOC FROM metrics";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let res = stmt.query_map(NO_PARAMS, |row| Ok(MetricTotals {
+            total_dep_func_count: row.get(0)?,
+            used_dep_func_count: row.get(1)?,
+            total_dep_loc: row.get(2)?,
+            used_dep_loc: row.get(3)?,
+            total_loc: row.get(4)?
+        }));
+
+        match res {
+            Err(_why) => Vec::new(),
+            Ok(val) => val.filter_map(|x| x.ok()).collect()
+        }
+    }
+
+    /// Name, version, number of dependents and summed used and total function
+    /// counts of every dependency with more than `min_dependents` dependents.
+    pub fn get_dep_utilization(&self, min_dependents: u32) -> Vec<(String, String, i64, i64, i64)>{
+        let sql = "SELECT name, version, COUNT(*) AS dependents, SUM(used_count), SUM(total_count)
+            FROM dep_metrics
+            GROUP BY name, version
+            HAVING dependents > ?1";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let res = stmt.query_map(params![min_dependents], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)));
+
+        match res {
+            Err(_why) => Vec::new(),
+            Ok(val) => val.filter_map(|x| x.ok()).collect()
+        }
+    }
+
+    /// Number of used and never used functions of every `dep` row.
+    pub fn get_dep_function_usage(&self) -> Vec<(i64, i64)>{
+        let sql = "SELECT SUM(use_count > 0), SUM(use_count = 0) FROM dep_func_metrics GROUP BY dep_id";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let res = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)));
+
+        match res {
+            Err(_why) => Vec::new(),
+            Ok(val) => val.filter_map(|x| x.ok()).collect()
+        }
+    }
+
+    /// Number of analyzed crates that directly depend on a dependency, in any
+    /// version if `version` is `None`.
+    pub fn count_dependents(&self, name: &str, version: Option<&str>) -> i64{
+        let sql = "SELECT COUNT(DISTINCT crate_id) FROM dep_metrics WHERE name = ?1 AND (?2 IS NULL OR version = ?2)";
+        match self.conn.query_row(sql, params![name, version], |row| row.get(0)) {
+            Err(why) => {
+                println!("{:?}", why);
+                0
+            },
+            Ok(count) => count
+        }
+    }
+
+    /// Functions recorded for a dependency with how many analyzed crates use
+    /// them, most used first. Counts of several versions are summed.
+    pub fn get_dep_function_uses(&self, name: &str, version: Option<&str>) -> Vec<(String, i64, bool)>{
+        let sql = "SELECT func, SUM(use_count) AS uses, MAX(has_LOC)
+            FROM dep_func_metrics
+            WHERE dep_id IN (SELECT id FROM dep WHERE name = ?1 AND (?2 IS NULL OR version = ?2))
+            GROUP BY func
+            ORDER BY uses DESC, func";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let res = stmt.query_map(params![name, version], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)));
+
+        match res {
+            Err(_why) => Vec::new(),
+            Ok(val) => val.filter_map(|x| x.ok()).collect()
+        }
+    }
+
+    /// Externally visible functions of a dependency with how many analyzed
+    /// crates use them, over all versions if `version` is `None`.
+    pub fn get_dep_public_function_uses(&self, name: &str, version: Option<&str>) -> Vec<(String, i64)>{
+        let sql = "SELECT func, SUM(use_count)
+            FROM dep_func_metrics
+            WHERE dep_id IN (SELECT id FROM dep WHERE name = ?1 AND (?2 IS NULL OR version = ?2))
+            GROUP BY func
+            HAVING MAX(is_public) = 1
+            ORDER BY func";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let res = stmt.query_map(params![name, version], |row| Ok((row.get(0)?, row.get(1)?)));
+
+        match res {
+            Err(_why) => Vec::new(),
+            Ok(val) => val.filter_map(|x| x.ok()).collect()
+        }
+    }
+
+    /// Crate versions whose latest failure has the given error kind and that
+    /// have no metrics yet, most recent first.
+    pub fn get_errors_of_kind(&self, kind: &str, limit: u32) -> Vec<(String, String, String)>{
+        let sql = "SELECT name, version, IFNULL(message, error) FROM metric_errors
+            WHERE id IN (SELECT MAX(id) FROM metric_errors GROUP BY name, version)
+            AND IFNULL(kind, 'unknown') = ?1
+            AND NOT EXISTS (SELECT 1 FROM metrics
+                WHERE crate_name = metric_errors.name AND crate_version = metric_errors.version)
+            ORDER BY id DESC
+            LIMIT ?2";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let res = stmt.query_map(params![kind, limit], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)));
+
+        match res {
+            Err(_why) => Vec::new(),
+            Ok(val) => val.filter_map(|x| x.ok()).collect()
+        }
+    }
+
+    /// Replaces everything stored for a crate version, metrics of an earlier
+    /// successful run included, with the new error in one transaction.
+    pub fn replace_error(&self, error: &AnalysisError, crate_name: &String, crate_version: &String) -> Result<()>{
+        self.begin_transaction();
+        let result = self.delete_crate_rows(crate_name, crate_version)
+            .and_then(|_| self.insert_error_row(error, crate_name, crate_version));
+        self.finish_transaction(result)
+    }
+
+    /// Crate versions that already have metrics or errors recorded.
+    pub fn get_processed_crates(&self) -> HashSet<(String, String)>{
+        let mut processed = HashSet::new();
+        for sql in &["SELECT crate_name, crate_version FROM metrics", "SELECT name, version FROM metric_errors"] {
+            let mut stmt = self.conn.prepare(sql).unwrap();
+            let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+            for row in rows {
+                match row {
+                    Err(why) => println!("{:?}", why),
+                    Ok(val) => {
+                        processed.insert(val);
+                    }
+                }
+            }
+        }
+        processed
+    }
+
+    /// Deletes the rows of a crate version and takes back the function uses it
+    /// counted. Uses counted before `dep_func_uses` existed stay.
+    fn delete_crate_rows(&self, crate_name: &String, crate_version: &String) -> Result<()>{
+        self.conn.execute(
+            "UPDATE dep_func_metrics SET use_count = use_count - (
+                SELECT COUNT(*) FROM dep_func_uses
+                WHERE func_id = dep_func_metrics.rowid
+                    AND crate_id IN (SELECT id FROM metrics WHERE crate_name = ?1 AND crate_version = ?2))
+            WHERE rowid IN (SELECT func_id FROM dep_func_uses
+                WHERE crate_id IN (SELECT id FROM metrics WHERE crate_name = ?1 AND crate_version = ?2))",
+            params![crate_name, crate_version]
+        )?;
+        self.conn.execute(
+            "DELETE FROM dep_func_uses WHERE crate_id IN (SELECT id FROM metrics WHERE crate_name = ?1 AND crate_version = ?2)",
+            params![crate_name, crate_version]
+        )?;
+        self.conn.execute(
+            "DELETE FROM dep_metrics WHERE crate_id IN (SELECT id FROM metrics WHERE crate_name = ?1 AND crate_version = ?2)",
+            params![crate_name, crate_version]
+        )?;
+        self.conn.execute(
+            "DELETE FROM dep_path_metrics WHERE crate_id IN (SELECT id FROM metrics WHERE crate_name = ?1 AND crate_version = ?2)",
+            params![crate_name, crate_version]
+        )?;
+        self.conn.execute(
+            "DELETE FROM metrics WHERE crate_name = ?1 AND crate_version = ?2",
+            params![crate_name, crate_version]
+        )?;
+        self.conn.execute(
+            "DELETE FROM metric_errors WHERE name = ?1 AND version = ?2",
+            params![crate_name, crate_version]
+        )?;
+        Ok(())
+    }
+
+    fn insert_metric_rows(&self, metrics: &Metrics, crate_name: &String, crate_version: &String) -> Result<()>{
+        self.conn.execute(
             "INSERT INTO metrics (
                 crate_name, 
                 crate_version, 
@@ -292,8 +706,11 @@ impl SqliteHandler{
                 total_dep_func_count_with_LOC,
                 used_dep_func_count_with_LOC,
                 total_dep_public_func_count_with_LOC,
-                used_dep_public_func_count_with_LOC) 
-                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)",
+                used_dep_public_func_count_with_LOC,
+                direct_dep_func_count,
+                max_dep_depth,
+                mean_dep_depth) 
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25)",
             params![crate_name,
                 crate_version,
                 metrics.TotalFuncCount as u32,
@@ -315,52 +732,54 @@ impl SqliteHandler{
                 metrics.total_dep_func_count_with_LOC as u32,
                 metrics.used_dep_func_count_with_LOC as u32,
                 metrics.total_dep_public_func_count_with_LOC as u32,
-                metrics.used_dep_public_func_count_with_LOC as u32]
-        );
-        match result {
-            Err(why) => println!("{:?}", why),
-            Ok(_) => {
-                let id = self.conn.last_insert_rowid();
-                for dep_metric in &metrics.depMetrics{
-                    let res = self.conn.execute(
-                        "INSERT INTO dep_metrics (name, version, total_count, used_count, total_LOC, used_LOC, crate_id, total_count_with_LOC, used_count_with_LOC) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-                        params![&dep_metric.crate_name,
-                            dep_metric.crate_version,
-                            dep_metric.totalCount as u32,
-                            dep_metric.usedCount as u32,
-                            dep_metric.total_loc as u32,
-                            dep_metric.used_loc as u32,
-                            id,
-                            dep_metric.total_count_with_LOC as u32,
-                            dep_metric.used_count_with_LOC as u32]
-                    );
-                    match res {
-                        Err(why) => {
-                            println!("{:?}", why);
-                        },
-                        Ok(_) => ()
-                    }
-                }
-
-                self.begin_transaction();
+                metrics.used_dep_public_func_count_with_LOC as u32,
+                metrics.direct_dep_func_count as u32,
+                metrics.max_dep_depth as u32,
+                metrics.mean_dep_depth]
+        )?;
+        let id = self.conn.last_insert_rowid();
+        for dep_metric in &metrics.depMetrics{
+            self.conn.execute(
+                "INSERT INTO dep_metrics (name, version, total_count, used_count, total_LOC, used_LOC, crate_id, total_count_with_LOC, used_count_with_LOC) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![&dep_metric.crate_name,
+                    dep_metric.crate_version,
+                    dep_metric.totalCount as u32,
+                    dep_metric.usedCount as u32,
+                    dep_metric.total_loc as u32,
+                    dep_metric.used_loc as u32,
+                    id,
+                    dep_metric.total_count_with_LOC as u32,
+                    dep_metric.used_count_with_LOC as u32]
+            )?;
+        }
+        for path_metric in &metrics.path_metrics{
+            self.conn.execute(
+                "INSERT INTO dep_path_metrics (crate_id, dep_name, dep_version, kind, path, total_count, used_count, total_LOC, used_LOC) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![id,
+                    path_metric.dep_name,
+                    path_metric.dep_version,
+                    path_metric.kind.as_str(),
+                    path_metric.path,
+                    path_metric.total_count as u32,
+                    path_metric.used_count as u32,
+                    path_metric.total_loc as u32,
+                    path_metric.used_loc as u32]
+            )?;
+        }
 
-                for funcs in &metrics.used_funcs{
-                    let dep_id = self.get_dep_id(&funcs.0, &funcs.1);
-                    for func in &funcs.2{
-                        self.update_or_insert_func(&dep_id, &func.0, func.1);
-                    }
-                }
-                self.end_transaction();
-                self.begin_transaction();
-                for funcs in &metrics.unused_funcs{
-                    let dep_id = self.get_dep_id(&funcs.0, &funcs.1);
-                    for func in &funcs.2{
-                        self.insert_unused_func(&dep_id, &func.0, func.1)
-                    }
-                }
-                self.end_transaction();
+        for funcs in &metrics.used_funcs{
+            let dep_id = self.get_dep_id(&funcs.0, &funcs.1);
+            for func in &funcs.2{
+                self.update_or_insert_func(id, &dep_id, &func.def_id, func.has_loc, func.is_externally_visible)?;
             }
         }
+        for funcs in &metrics.unused_funcs{
+            let dep_id = self.get_dep_id(&funcs.0, &funcs.1);
+            for func in &funcs.2{
+                self.insert_unused_func(&dep_id, &func.def_id, func.has_loc, func.is_externally_visible)
+            }
+        }
+        Ok(())
     }
 
     // pub fn insert_error(&self, crate_name: &String, error_message: &String, status: String){
@@ -376,4 +795,115 @@ impl SqliteHandler{
     //         Ok(_val) => return
     //     }
     // }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_calculator::DepFunc;
+    use std::path::PathBuf;
+
+    fn metrics(funcs: &[&str]) -> Metrics {
+        let used = funcs.iter().map(|f| DepFunc {
+            def_id: f.to_string(),
+            has_loc: true,
+            is_externally_visible: true,
+            depth: Some(1)
+        }).collect();
+        Metrics { used_funcs: vec![("dep".to_string(), "1.0.0".to_string(), used)], ..Default::default() }
+    }
+
+    fn use_counts(db: &SqliteHandler) -> Vec<(String, i64)> {
+        let mut stmt = db.conn.prepare("SELECT func, use_count FROM dep_func_metrics ORDER BY func").unwrap();
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    fn counts(uses: &[(&str, i64)]) -> Vec<(String, i64)> {
+        uses.iter().map(|(func, count)| (func.to_string(), *count)).collect()
+    }
+
+    #[test]
+    fn forced_runs_take_back_the_uses_of_failed_crates() {
+        let db = SqliteHandler::new(":memory:");
+        let (name, version) = ("app".to_string(), "0.1.0".to_string());
+        db.insert_metric(&metrics(&["dep::a", "dep::b"]), &name, &version).unwrap();
+        assert_eq!(use_counts(&db), counts(&[("dep::a", 1), ("dep::b", 1)]));
+
+        let error = AnalysisError::CallgraphMissing(PathBuf::from("callgraph.json"));
+        db.replace_error(&error, &name, &version).unwrap();
+        assert_eq!(use_counts(&db), counts(&[("dep::a", 0), ("dep::b", 0)]));
+
+        db.replace_metric(&metrics(&["dep::a"]), &name, &version).unwrap();
+        assert_eq!(use_counts(&db), counts(&[("dep::a", 1), ("dep::b", 0)]));
+    }
+
+    #[test]
+    fn replacing_metrics_counts_every_crate_once() {
+        let db = SqliteHandler::new(":memory:");
+        let version = "0.1.0".to_string();
+        db.insert_metric(&metrics(&["dep::a"]), &"other".to_string(), &version).unwrap();
+        for _ in 0..2 {
+            db.replace_metric(&metrics(&["dep::a", "dep::b"]), &"app".to_string(), &version).unwrap();
+        }
+        assert_eq!(use_counts(&db), counts(&[("dep::a", 2), ("dep::b", 1)]));
+    }
+
+    #[test]
+    fn error_summary_counts_each_failed_version_once() {
+        let db = SqliteHandler::new(":memory:");
+        let version = "0.1.0".to_string();
+        let missing_graph = AnalysisError::CallgraphMissing(PathBuf::from("callgraph.json"));
+        let missing_lock = AnalysisError::MissingLockfile(PathBuf::from("Cargo.lock"));
+
+        // Reruns only count with their latest error.
+        db.insert_error(&missing_graph, &"app".to_string(), &version);
+        db.insert_error(&missing_graph, &"app".to_string(), &version);
+        db.insert_error(&missing_lock, &"app".to_string(), &version);
+        db.insert_error(&missing_graph, &"bpp".to_string(), &version);
+        db.insert_error(&missing_graph, &"bpp".to_string(), &"0.2.0".to_string());
+        // Versions that succeeded later don't count at all.
+        db.insert_error(&missing_graph, &"cpp".to_string(), &version);
+        db.insert_metric(&metrics(&[]), &"cpp".to_string(), &version).unwrap();
+
+        assert_eq!(db.get_error_summary(), vec![
+            ("callgraph_missing".to_string(), "load_graph".to_string(), 2),
+            ("missing_lockfile".to_string(), "lockfile".to_string(), 1)
+        ]);
+        let failed: Vec<_> = db.get_errors_of_kind("callgraph_missing", 10).into_iter()
+            .map(|(name, version, _)| (name, version))
+            .collect();
+        assert_eq!(failed, vec![
+            ("bpp".to_string(), "0.2.0".to_string()),
+            ("bpp".to_string(), version.clone())
+        ]);
+    }
+
+    #[test]
+    fn crate_states_need_the_expected_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert!(!has_crate_state_table(&conn));
+        conn.execute("CREATE TABLE crates (name TEXT, version TEXT, status INT)", NO_PARAMS).unwrap();
+        assert!(!has_crate_state_table(&conn));
+        conn.execute("ALTER TABLE crates ADD COLUMN state INT", NO_PARAMS).unwrap();
+        assert!(has_crate_state_table(&conn));
+    }
+
+    #[test]
+    fn analysis_states_leave_the_prazi_table_alone() {
+        let db = SqliteHandler::new(":memory:");
+        db.conn.execute("CREATE TABLE crates (name TEXT, version TEXT, state INT)", NO_PARAMS).unwrap();
+        db.conn.execute("INSERT INTO crates VALUES ('app', '0.1.0', 5)", NO_PARAMS).unwrap();
+        let (name, version) = ("app".to_string(), "0.1.0".to_string());
+        db.set_analysis_state(&name, &version, MainCrateState::AnalysisFailed);
+        db.set_analysis_state(&name, &version, MainCrateState::Analyzed);
+
+        let states = db.get_crate_states().unwrap();
+        assert_eq!(states.get(&(name.clone(), version.clone())), Some(&MainCrateState::FullCallgraph));
+        let analyzed: Vec<i64> = db.conn.prepare("SELECT state FROM analysis_state").unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0)).unwrap()
+            .map(|state| state.unwrap())
+            .collect();
+        assert_eq!(analyzed, vec![MainCrateState::Analyzed as i64]);
+    }
+}